@@ -0,0 +1,338 @@
+extern crate chrono;
+use chrono::{DateTime, Duration, Utc};
+
+use std::cmp;
+
+use chart::chart::Chart;
+use chart::point::Point;
+
+// A way of summarizing the points in a window of a chart as a single value.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum Aggregation {
+  Min,
+  Max,
+  Mean,
+  Sum,
+  Count,
+
+  // The value of the earliest / latest point.
+  First,
+  Last,
+
+  // The population standard deviation of the values.
+  StdDev,
+}
+
+// A running summary of a set of points, enough to find any `Aggregation` of them. The mean and
+// spread are kept with Welford's method rather than as sums of squares, so that the standard
+// deviation stays accurate for values that are large compared to how much they vary.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct Summary {
+  pub count: usize,
+  pub min: f64,
+  pub max: f64,
+  pub sum: f64,
+  pub mean: f64,
+
+  // The sum of squared differences from the mean.
+  pub spread: f64,
+
+  pub first: Option<Point>,
+  pub last: Option<Point>,
+}
+
+impl Summary {
+  pub fn new() -> Summary {
+    Summary {
+      count: 0,
+      min: f64::INFINITY,
+      max: f64::NEG_INFINITY,
+      sum: 0.0,
+      mean: 0.0,
+      spread: 0.0,
+      first: None,
+      last: None,
+    }
+  }
+
+  pub fn from_points(points: &[Point]) -> Summary {
+    let mut summary = Summary::new();
+    for point in points {
+      summary.add(point);
+    }
+    summary
+  }
+
+  // Add a point to the summary. Points are expected to be added in timestamp order.
+  pub fn add(&mut self, point: &Point) {
+    self.count += 1;
+    self.min = self.min.min(point.value);
+    self.max = self.max.max(point.value);
+    self.sum += point.value;
+
+    let difference = point.value - self.mean;
+    self.mean += difference / self.count as f64;
+    self.spread += difference * (point.value - self.mean);
+
+    if self.first.is_none() {
+      self.first = Some(point.clone());
+    }
+    self.last = Some(point.clone());
+  }
+
+  // Add all of the points of another summary to this one. The other summary's points are expected
+  // to come after this one's (Chan et al.'s method for combining the mean and spread).
+  pub fn merge(&mut self, other: &Summary) {
+    if other.count == 0 {
+      return;
+    }
+    if self.count == 0 {
+      *self = other.clone();
+      return;
+    }
+
+    let count = self.count + other.count;
+    let difference = other.mean - self.mean;
+    let weight = self.count as f64 * other.count as f64 / count as f64;
+    self.mean += difference * other.count as f64 / count as f64;
+    self.spread += other.spread + difference * difference * weight;
+
+    self.count = count;
+    self.min = self.min.min(other.min);
+    self.max = self.max.max(other.max);
+    self.sum += other.sum;
+    self.last = other.last.clone();
+  }
+
+  // The timestamps of the first and last points, unless there aren't any.
+  pub fn time_span(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    match (self.first.as_ref(), self.last.as_ref()) {
+      (Some(first), Some(last)) => Some((first.timestamp, last.timestamp)),
+      _ => None,
+    }
+  }
+
+  // The aggregation of the points added so far. Without any points, the count is zero and there's
+  // no other value.
+  pub fn value(&self, aggregation: Aggregation) -> Option<f64> {
+    if self.count == 0 {
+      return if aggregation == Aggregation::Count { Some(0.0) } else { None };
+    }
+
+    let value = match aggregation {
+      Aggregation::Min => self.min,
+      Aggregation::Max => self.max,
+      Aggregation::Mean => self.mean,
+      Aggregation::Sum => self.sum,
+      Aggregation::Count => self.count as f64,
+      Aggregation::First => self.first.as_ref().unwrap().value,
+      Aggregation::Last => self.last.as_ref().unwrap().value,
+      Aggregation::StdDev => (self.spread / self.count as f64).sqrt(),
+    };
+    Some(value)
+  }
+}
+
+impl Chart {
+  // Aggregate the points from `start` up to (but not including) `end`.
+  pub fn aggregate(
+    &self,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    aggregation: Aggregation,
+  ) -> Option<f64> {
+    debug!("CALLING chart.aggregate({:?}, {:?}, {:?})", start, end, aggregation);
+    self.summarize(start, end).value(aggregation)
+  }
+
+  // Split the time from `start` up to `end` into buckets `width` long, and aggregate the points in
+  // each one. Each bucket includes its start but not its end, and the last bucket is cut short at
+  // `end` if needed. There's a point at the start of each bucket that has a value.
+  pub fn aggregate_buckets(
+    &self,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    width: Duration,
+    aggregation: Aggregation,
+  ) -> Vec<Point> {
+    debug!(
+      "CALLING chart.aggregate_buckets({:?}, {:?}, {:?}, {:?})",
+      start, end, width, aggregation,
+    );
+    if width <= Duration::zero() {
+      panic!("Aggregating needs a bucket width greater than zero, got {:?}!", width);
+    }
+
+    let mut buckets = vec![];
+    let mut bucket_start = start;
+    while bucket_start < end {
+      let bucket_end = cmp::min(bucket_start + width, end);
+      if let Some(value) = self.summarize(bucket_start, bucket_end).value(aggregation) {
+        buckets.push(Point::new(value, bucket_start));
+      }
+      bucket_start = bucket_end;
+    }
+
+    buckets
+  }
+
+  // Summarize the points from `start` up to (but not including) `end`. Subtrees of the index that
+  // are entirely inside the range are taken from the summaries in their nodes, so only the leaves
+  // at either end of the range have their points looked at one by one.
+  pub fn summarize(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Summary {
+    if self.index.len() == 0 {
+      panic!("Index is not built, this is a requirement to summarize the chart!");
+    }
+
+    let mut summary = Summary::new();
+    self.summarize_index_node_between(0, start, end, &mut summary);
+    summary
+  }
+
+  fn summarize_index_node_between(
+    &self,
+    node_index: usize,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    summary: &mut Summary,
+  ) {
+    let node = &self.index[node_index];
+    let (first, last) = match node.summary.time_span() {
+      Some(time_span) => time_span,
+      None => return,
+    };
+    if last < start || first >= end {
+      return;
+    }
+    if first >= start && last < end {
+      summary.merge(&node.summary);
+      return;
+    }
+
+    match node.data {
+      Some(ref data) => {
+        for point in data {
+          if point.timestamp >= start && point.timestamp < end {
+            summary.add(point);
+          }
+        }
+      },
+      None => {
+        for &child_index in &node.children {
+          self.summarize_index_node_between(child_index, start, end, summary);
+        }
+      },
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::{Duration, Utc, TimeZone};
+  use chart::aggregation::Aggregation;
+  use chart::point::Point;
+  use chart::test_helpers::chart_counting_up_every_minute;
+
+  #[test]
+  fn it_aggregates_points_in_a_window() {
+    let chart = chart_counting_up_every_minute(1, 60);
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 10, 0);
+    let end = Utc.ymd(2018, 1, 1).and_hms(9, 20, 0);
+
+    // 11 up to 20
+    assert_eq!(chart.aggregate(start, end, Aggregation::Min), Some(11.0));
+    assert_eq!(chart.aggregate(start, end, Aggregation::Max), Some(20.0));
+    assert_eq!(chart.aggregate(start, end, Aggregation::Sum), Some(155.0));
+    assert_eq!(chart.aggregate(start, end, Aggregation::Mean), Some(15.5));
+    assert_eq!(chart.aggregate(start, end, Aggregation::Count), Some(10.0));
+    assert_eq!(chart.aggregate(start, end, Aggregation::First), Some(11.0));
+    assert_eq!(chart.aggregate(start, end, Aggregation::Last), Some(20.0));
+    let stddev = chart.aggregate(start, end, Aggregation::StdDev).unwrap();
+    assert!((stddev - 8.25f64.sqrt()).abs() < 1e-12);
+
+    // Nothing in the window
+    let empty_start = Utc.ymd(2018, 1, 1).and_hms(10, 0, 0);
+    let empty_end = Utc.ymd(2018, 1, 1).and_hms(11, 0, 0);
+    assert_eq!(chart.aggregate(empty_start, empty_end, Aggregation::Count), Some(0.0));
+    assert_eq!(chart.aggregate(empty_start, empty_end, Aggregation::Mean), None);
+    assert_eq!(chart.aggregate(end, start, Aggregation::Sum), None);
+  }
+
+  #[test]
+  fn it_keeps_the_standard_deviation_accurate_for_large_values() {
+    let mut chart = chart_counting_up_every_minute(1, 0);
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 0, 0);
+    for i in 0..1000 {
+      let value = 1e9 + if i % 2 == 0 { 1.0 } else { -1.0 };
+      chart.insert(Point::new(value, start + Duration::seconds(i)));
+    }
+
+    let stddev = chart.aggregate(start, start + Duration::hours(1), Aggregation::StdDev).unwrap();
+    assert!((stddev - 1.0).abs() < 1e-6);
+  }
+
+  #[test]
+  fn it_aggregates_using_the_summaries_in_the_index() {
+    let mut chart = chart_counting_up_every_minute(1, 500);
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 0, 0);
+    for i in 0..200 {
+      chart.insert(Point::new(-(i as f64), start + Duration::seconds(i * 83 + 30)));
+      chart.remove(start + Duration::minutes(i * 2 + 1));
+    }
+
+    // Compare against going through every point in each window.
+    for &(from, to) in &[(0, 500), (1, 2), (17, 250), (123, 124), (250, 600), (-10, 5)] {
+      let window_start = start + Duration::minutes(from);
+      let window_end = start + Duration::minutes(to) + Duration::seconds(15);
+      let values: Vec<f64> = chart.points.iter()
+        .filter(|point| point.timestamp >= window_start && point.timestamp < window_end)
+        .map(|point| point.value)
+        .collect();
+
+      let summary = chart.summarize(window_start, window_end);
+      assert_eq!(summary.count, values.len());
+      assert_eq!(summary.value(Aggregation::Sum), Some(values.iter().sum()));
+      assert_eq!(summary.value(Aggregation::First), values.first().cloned());
+      assert_eq!(summary.value(Aggregation::Last), values.last().cloned());
+      let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+      let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+      assert_eq!(summary.value(Aggregation::Min), Some(min));
+      assert_eq!(summary.value(Aggregation::Max), Some(max));
+
+      let mean = values.iter().sum::<f64>() / values.len() as f64;
+      let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() /
+        values.len() as f64;
+      let stddev = summary.value(Aggregation::StdDev).unwrap();
+      assert!((stddev - variance.sqrt()).abs() < 1e-9 * variance.sqrt().max(1.0));
+    }
+  }
+
+  #[test]
+  fn it_aggregates_points_in_buckets() {
+    let chart = chart_counting_up_every_minute(1, 60);
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 10, 0);
+    let end = Utc.ymd(2018, 1, 1).and_hms(9, 35, 0);
+
+    let buckets = chart.aggregate_buckets(start, end, Duration::minutes(10), Aggregation::Sum);
+    assert_eq!(buckets, vec![
+      Point::new(155.0, start),
+      Point::new(255.0, start + Duration::minutes(10)),
+      Point::new(165.0, start + Duration::minutes(20)),
+    ]);
+
+    // Buckets without points only have a count
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 50, 0);
+    let end = Utc.ymd(2018, 1, 1).and_hms(10, 10, 0);
+    let width = Duration::minutes(5);
+    let buckets = chart.aggregate_buckets(start, end, width, Aggregation::Count);
+    let counts: Vec<f64> = buckets.iter().map(|point| point.value).collect();
+    assert_eq!(counts, vec![5.0, 5.0, 0.0, 0.0]);
+    assert_eq!(chart.aggregate_buckets(start, end, width, Aggregation::Max).len(), 2);
+  }
+}
@@ -1,6 +1,10 @@
 extern crate chrono;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chart::gap_policy::GapPolicy;
+use chart::interpolation::Interpolation;
 use chart::point::Point;
 use chart::point_index::PointIndex;
 
@@ -8,45 +12,119 @@ fn linear_interpolation(starting_value: f64, ending_value: f64, percentage: f64)
   starting_value + ((ending_value - starting_value) * percentage)
 }
 
+// The length of `duration` in nanoseconds. Durations too long to count in nanoseconds (a few
+// hundred years) fall back to millisecond precision.
+pub fn nanoseconds_in(duration: Duration) -> f64 {
+  match duration.num_nanoseconds() {
+    Some(nanoseconds) => nanoseconds as f64,
+    None => duration.num_milliseconds() as f64 * 1_000_000.0,
+  }
+}
+
+// The time from `start` to `end` in nanoseconds.
+pub fn duration_in_nanoseconds(start: DateTime<Utc>, end: DateTime<Utc>) -> f64 {
+  nanoseconds_in(end.signed_duration_since(start))
+}
+
+// The time from `start` to `end` in seconds, to nanosecond precision.
+pub fn seconds_between(start: DateTime<Utc>, end: DateTime<Utc>) -> f64 {
+  duration_in_nanoseconds(start, end) / 1_000_000_000.0
+}
+
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+// A version no chart has had before, to give a chart whenever its points change.
+pub fn next_version() -> u64 {
+  NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
 // A chart holds datapoints containing a timestamp and value.
 pub struct Chart {
   pub points: Vec<Point>,
   pub index: Vec<PointIndex>,
 
   pub max_index_node_capacity: usize,
+
+  // How values in between points are found.
+  pub interpolation: Interpolation,
+
+  // What the chart's value is in long gaps between points, and past either end.
+  pub gap_policy: GapPolicy,
+
+  // Changes every time the chart's points are changed, so that anything worked out from them
+  // ahead of time (like the leaves cached by a projection) can tell when it's out of date. No two
+  // charts ever share a version, so it also tells charts apart.
+  pub version: u64,
 }
 
 impl Chart {
+  // Make a new chart holding `points`, which must already be sorted by timestamp, with up to
+  // `max_index_node_capacity` entries in each node of its index. It interpolates linearly and has
+  // the default gap policy, and its index is built and ready to use.
+  pub fn new(points: Vec<Point>, max_index_node_capacity: usize) -> Chart {
+    let mut chart = Chart {
+      points: points,
+      index: vec![],
+      max_index_node_capacity: max_index_node_capacity,
+      interpolation: Interpolation::default(),
+      gap_policy: GapPolicy::default(),
+      version: 0,
+    };
+    chart.build_index();
+    chart
+  }
+
+  // Make a new chart holding `points`, which must already be sorted by timestamp, with the same
+  // settings as this one. Its index is built and ready to use.
+  pub fn with_points(&self, points: Vec<Point>) -> Chart {
+    let mut chart = Chart::new(points, self.max_index_node_capacity);
+    chart.interpolation = self.interpolation;
+    chart.gap_policy = self.gap_policy.clone();
+    chart
+  }
+
   pub fn get_value_vec(&self, timestamp: DateTime<Utc>) -> Option<f64> {
-    // Find the point before the passed-in timestamp
+    self.get_value_vec_with(timestamp, self.interpolation)
+  }
+
+  // Like `get_value_vec`, but interpolating between points with `interpolation` rather than the
+  // chart's own interpolation.
+  pub fn get_value_vec_with(
+    &self,
+    timestamp: DateTime<Utc>,
+    interpolation: Interpolation,
+  ) -> Option<f64> {
+    // Find the first point at or after the passed-in timestamp
+    let mut first_after = 0;
     let point_iterator = 0..self.points.len();
     for index in point_iterator.rev() {
       if self.points[index].timestamp < timestamp {
-        let point_before = &self.points[index];
-
-        // If at the most recent point, then no interpolation can be done. Return the final point.
-        if index == self.points.len()-1 {
-          return Some(point_before.value);
-        }
-
-        let point_after = &self.points[index+1];
-        return Some(self.interpolate_between_points(timestamp, &point_before, &point_after));
+        first_after = index+1;
+        break;
       }
     }
 
-    return None
+    // Gather the points on either side, closest first.
+    let count = self.points_needed_around(interpolation);
+    let before: Vec<Point> = self.points[..first_after].iter().rev().take(count).cloned().collect();
+    let after: Vec<Point> = self.points[first_after..].iter().take(count).cloned().collect();
+    self.interpolate_around(timestamp, &before, &after, interpolation)
   }
 
   pub fn interpolate_between_points(&self, timestamp: DateTime<Utc>, point_before: &Point, point_after: &Point) -> f64 {
+    // Two points at the same time have nothing to interpolate between, so use the later one.
+    let time_between_points_ns = duration_in_nanoseconds(
+      point_before.timestamp,
+      point_after.timestamp,
+    );
+    if time_between_points_ns == 0.0 {
+      return point_after.value;
+    }
+
     // Figure out the percentage between the points point before and the point after that
     // `timestamp` represents.
-    let time_to_timestamp_ms = (
-      timestamp.timestamp_millis() - point_before.timestamp.timestamp_millis()
-    ) as f64;
-    let time_between_points_ms = (
-      point_after.timestamp.timestamp_millis() - point_before.timestamp.timestamp_millis()
-    ) as f64;
-    let percentage_between_points = time_to_timestamp_ms / time_between_points_ms;
+    let time_to_timestamp_ns = duration_in_nanoseconds(point_before.timestamp, timestamp);
+    let percentage_between_points = time_to_timestamp_ns / time_between_points_ns;
 
     // Don't interpolate if not required
     if percentage_between_points == 0.0 {
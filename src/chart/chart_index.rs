@@ -1,117 +1,514 @@
 extern crate chrono;
-use chrono::{DateTime, Utc, NaiveDateTime};
+use chrono::{DateTime, Utc};
 
-use chart::chart::Chart;
+use std::cmp;
+use std::mem;
+use std::sync::OnceLock;
+
+use chart::aggregation::Summary;
+use chart::chart::{Chart, next_version};
+use chart::interpolation::Interpolation;
+use chart::point::Point;
 use chart::point_index::PointIndex;
 use chart::projection::Projection;
 use chart::projection::ProjectionDisposable;
 
+// Split `length` items into as few groups of at most `capacity` items as possible, keeping the
+// groups as close to the same size as each other as possible. Returns the size of each group.
+fn group_sizes(length: usize, capacity: usize) -> Vec<usize> {
+  let groups = length.div_ceil(capacity);
+  (0..groups).map(|group| length / groups + if group < length % groups { 1 } else { 0 }).collect()
+}
+
 impl Chart {
+  // The most points that a leaf in the index can hold.
+  fn leaf_capacity(&self) -> usize {
+    cmp::max(self.max_index_node_capacity, 1)
+  }
+
+  // The most children that an inner node in the index can have. This is at least three, so that
+  // both halves of a split node always end up with two children or more.
+  fn node_capacity(&self) -> usize {
+    cmp::max(self.max_index_node_capacity, 3)
+  }
+
+  fn capacity_of_index_node(&self, node_index: usize) -> usize {
+    if self.index[node_index].is_leaf() {
+      self.leaf_capacity()
+    } else {
+      self.node_capacity()
+    }
+  }
+
+  // The fewest points or children that a node other than the root can hold before it has to take
+  // some from a sibling or be merged into one.
+  fn minimum_length_of_index_node(&self, node_index: usize) -> usize {
+    self.capacity_of_index_node(node_index).div_ceil(2)
+  }
+
+  // If the node at `node_index` holds more than its capacity, split it in half and move the upper
+  // half into a new node just after it. This adds a child to the parent, so the parent may need
+  // splitting too. Either way, the summaries of the node and everything above it are brought up
+  // to date.
   pub fn rebalance_index_node(&mut self, node_index: usize) {
-    /* println!("\n\nRebalancing {}", node_index); */
+    if self.index[node_index].len() <= self.capacity_of_index_node(node_index) {
+      self.summarize_up_from_index_node(node_index);
+      return;
+    }
 
-    let mut rebalanced_node = false;
-    let mut less: Option<PointIndex> = None;
-    let mut more: Option<PointIndex> = None;
+    // The root always lives at position zero. To split it, first move it out of the way and put
+    // a new root above it.
+    let node_index = if node_index == 0 { self.grow_index() } else { node_index };
+    debug!("Splitting index node {}", node_index);
 
-    // Borrow a mutable reference to the node at `node_index`.
-    {
+    let new_index = self.index.len();
+    let parent_index = self.index[node_index].parent;
+    let (split_timestamp, new_node) = {
       let node = &mut self.index[node_index];
-
+      let split_at = node.len() / 2;
       match node.data {
-        Some(ref data) => {
-          // If this node isn't balanced...
-          if data.len() > self.max_index_node_capacity {
-            rebalanced_node = true;
-            let data_length = data.len();
-
-            // Calculate average timestamp of all data items in the node. Set that average timestamp
-            // equal to `node.timestamp` (as it's the natural breaking point into two smaller nodes)
-            let mut average_timestamp: i64 = 0;
-            for item in data {
-              average_timestamp += item.timestamp.timestamp();
-            }
-            average_timestamp /= data_length as i64;
-
-            let timestamp_split_point = DateTime::from_utc(
-              NaiveDateTime::from_timestamp(average_timestamp, 0),
-              Utc,
-            );
-            node.timestamp = Some(timestamp_split_point);
-
-            /* print!("Split timestamp: {:?}\n", timestamp_split_point); */
-
-            // Create a two new nodes. All less than the average timestamp goes in one node, all more
-            // than it goes into the other. The timestamp in these nodes is None.
-
-            let mut index_of_timestamp_split: usize = data_length-1;
-            for index in 0..data_length {
-              if data[index].timestamp > timestamp_split_point {
-                index_of_timestamp_split = index;
-                break;
-              }
-            }
-
-            // Note: if the timestamp is equal to the split timestamp, it'll end up on the `less`
-            // side.
-
-            /* print!("Split index: {:?}\n", index_of_timestamp_split); */
-
-            less = Some(PointIndex {
-              timestamp: None,
-              less: 0,
-              more: 0,
-              parent: node_index,
-              data: Some(data[..index_of_timestamp_split].to_vec()),
-            });
-
-            more = Some(PointIndex {
-              timestamp: None,
-              less: 0,
-              more: 0,
-              parent: node_index,
-              data: Some(data[index_of_timestamp_split..].to_vec()),
-            });
-          }
+        Some(ref mut data) => {
+          let upper_data = data.split_off(split_at);
+          let split_timestamp = upper_data[0].timestamp;
+          let mut new_node = PointIndex::new_leaf(upper_data, parent_index);
+          new_node.less = Some(node_index);
+          new_node.more = node.more;
+          (split_timestamp, new_node)
+        },
+        None => {
+          let upper_children = node.children.split_off(split_at);
+          let mut upper_keys = node.keys.split_off(split_at-1);
+          let split_timestamp = upper_keys.remove(0);
+          (split_timestamp, PointIndex::new_node(upper_keys, upper_children, parent_index))
+        },
+      }
+    };
+
+    // Point everything that should now refer to the new node at it.
+    if new_node.is_leaf() {
+      if let Some(more_index) = new_node.more {
+        self.index[more_index].less = Some(new_index);
+      }
+      self.index[node_index].more = Some(new_index);
+    } else {
+      for &child_index in &new_node.children {
+        self.index[child_index].parent = new_index;
+      }
+    }
+    self.index.push(new_node);
+    self.summarize_index_node(node_index);
+    self.summarize_index_node(new_index);
+
+    {
+      let parent = &mut self.index[parent_index];
+      let position = parent.children.iter().position(|&child| child == node_index).unwrap();
+      parent.children.insert(position+1, new_index);
+      parent.keys.insert(position, split_timestamp);
+    }
+    self.rebalance_index_node(parent_index);
+  }
+
+  // Move the root to the end of the index, and put an inner node with the old root as its only
+  // child in its place. Returns the old root's new position.
+  fn grow_index(&mut self) -> usize {
+    let moved_index = self.index.len();
+    let new_root = PointIndex::new_node(vec![], vec![moved_index], 0);
+    let moved = mem::replace(&mut self.index[0], new_root);
+    for &child_index in &moved.children {
+      self.index[child_index].parent = moved_index;
+    }
+    self.index.push(moved);
+    moved_index
+  }
+
+  // Replace a root that has a single child with that child, removing a level from the tree.
+  fn shrink_index(&mut self) {
+    let child_index = self.index[0].children[0];
+    let mut child = mem::replace(&mut self.index[child_index], PointIndex::new_default_value());
+    child.parent = 0;
+    for &grandchild_index in &child.children {
+      self.index[grandchild_index].parent = 0;
+    }
+    self.index[0] = child;
+    self.remove_index_node(child_index);
+  }
+
+  // Build the index from `points`, which must already be sorted by timestamp. Points are packed
+  // into evenly sized leaves, and then inner nodes are added on top of those one level at a time
+  // until a single root is left.
+  pub fn build_index(&mut self) {
+    self.version = next_version();
+    let leaf_capacity = self.leaf_capacity();
+    let node_capacity = self.node_capacity();
+
+    // The root always lives at position zero, so hold its spot while the rest of the tree is built.
+    self.index = vec![PointIndex::new_leaf(vec![], 0)];
+    if self.points.len() <= leaf_capacity {
+      self.index[0] = PointIndex::new_leaf(self.points.clone(), 0);
+      return;
+    }
+
+    // Each level is a list of node positions, along with the first timestamp under each node.
+    let mut level: Vec<(usize, DateTime<Utc>)> = vec![];
+    let mut start = 0;
+    for size in group_sizes(self.points.len(), leaf_capacity) {
+      let leaf_index = self.index.len();
+      let mut leaf = PointIndex::new_leaf(self.points[start..start+size].to_vec(), 0);
+      if let Some(&(previous_index, _)) = level.last() {
+        leaf.less = Some(previous_index);
+        self.index[previous_index].more = Some(leaf_index);
+      }
+      self.index.push(leaf);
+      level.push((leaf_index, self.points[start].timestamp));
+      start += size;
+    }
+
+    while level.len() > node_capacity {
+      let mut next_level = vec![];
+      let mut start = 0;
+      for size in group_sizes(level.len(), node_capacity) {
+        let group = &level[start..start+size];
+        next_level.push((self.add_index_node(self.index.len(), group), group[0].1));
+        start += size;
+      }
+      level = next_level;
+    }
+    self.add_index_node(0, &level);
+  }
+
+  // Put an inner node over `children` at `node_index`, which is either zero for the root or the
+  // end of the index for any other node.
+  fn add_index_node(&mut self, node_index: usize, children: &[(usize, DateTime<Utc>)]) -> usize {
+    let keys = children[1..].iter().map(|&(_, timestamp)| timestamp).collect();
+    let child_indexes = children.iter().map(|&(child_index, _)| child_index).collect();
+    let node = PointIndex::new_node(keys, child_indexes, 0);
+    if node_index == self.index.len() {
+      self.index.push(node);
+    } else {
+      self.index[node_index] = node;
+    }
+
+    for &(child_index, _) in children {
+      self.index[child_index].parent = node_index;
+    }
+    self.summarize_index_node(node_index);
+    node_index
+  }
+
+  // Bring the summary of the node at `node_index` up to date with its points, or with the summaries
+  // of its children.
+  fn summarize_index_node(&mut self, node_index: usize) {
+    let summary = match self.index[node_index].data {
+      Some(ref data) => Summary::from_points(data),
+      None => {
+        let mut summary = Summary::new();
+        for &child_index in &self.index[node_index].children {
+          summary.merge(&self.index[child_index].summary);
         }
-        None => ()
+        summary
+      },
+    };
+    self.index[node_index].summary = summary;
+    self.index[node_index].sketch = OnceLock::new();
+  }
+
+  // Bring the summaries of the node at `node_index` and every node above it up to date.
+  fn summarize_up_from_index_node(&mut self, node_index: usize) {
+    let mut node_index = node_index;
+    loop {
+      self.summarize_index_node(node_index);
+      if node_index == 0 {
+        return;
       }
+      node_index = self.index[node_index].parent;
+    }
+  }
+
+  // Add a single point to the chart after the index has been built. The point is put into
+  // `points` and into the leaf that covers its timestamp, and that leaf is split if it grows past
+  // `max_index_node_capacity`. Points with a timestamp equal to an existing point are placed after
+  // it.
+  pub fn insert(&mut self, point: Point) {
+    debug!("CALLING chart.insert({:?})", point);
+    let position = self.points.partition_point(|p| p.timestamp <= point.timestamp);
+    self.points.insert(position, point.clone());
+    self.version = next_version();
+
+    // Without an index, there's nothing else to keep up to date. `build_index` will pick up the
+    // point later.
+    if self.index.len() == 0 {
+      return;
     }
 
-    if rebalanced_node {
-      self.index[node_index].data = None;
+    if let Some(node_index) = self.lookup_in_index(point.timestamp) {
+      if let Some(ref mut data) = self.index[node_index].data {
+        let position = data.partition_point(|p| p.timestamp <= point.timestamp);
+        data.insert(position, point);
+      }
+      self.rebalance_index_node(node_index);
+    }
+  }
 
-      // Set `node.less` and `node.more` equal to the indexes of the two new nodes.
-      let unwrapped_less = less.unwrap();
-      let unwrapped_more = more.unwrap();
+  // Remove the point at exactly `timestamp` from the chart, returning it. If more than one point
+  // shares the timestamp, the first one is removed.
+  pub fn remove(&mut self, timestamp: DateTime<Utc>) -> Option<Point> {
+    debug!("CALLING chart.remove({:?})", timestamp);
+    let position = self.points.partition_point(|p| p.timestamp < timestamp);
+    if position == self.points.len() || self.points[position].timestamp != timestamp {
+      return None;
+    }
 
-      self.index.push(unwrapped_less);
-      let less_index = self.index.len()-1;
-      self.index[node_index].less = less_index;
+    let point = self.points.remove(position);
+    self.version = next_version();
+    if self.index.len() > 0 {
+      self.remove_from_index(&point);
+    }
+    Some(point)
+  }
 
-      self.index.push(unwrapped_more);
-      let more_index = self.index.len()-1;
-      self.index[node_index].more = more_index;
+  // Remove every point with a timestamp between `start` and `end` (both inclusive) from the chart,
+  // returning the removed points in order. When that's more than a tenth of the chart, the index
+  // is built again from what's left, which is quicker than taking the points out of it. Otherwise
+  // the points are taken out of each leaf all at once.
+  pub fn remove_range(&mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<Point> {
+    debug!("CALLING chart.remove_range({:?}, {:?})", start, end);
+    let from = self.points.partition_point(|p| p.timestamp < start);
+    let to = self.points.partition_point(|p| p.timestamp <= end);
+    if from >= to {
+      return vec![];
+    }
 
-      // Call this function on each new node.
-      self.rebalance_index_node(less_index);
-      self.rebalance_index_node(more_index);
+    let removed: Vec<Point> = self.points.drain(from..to).collect();
+    self.version = next_version();
+    if self.index.len() > 0 {
+      if removed.len() * 10 > self.points.len() + removed.len() {
+        self.build_index();
+      } else {
+        self.remove_range_from_index(start, end);
+      }
     }
+    removed
   }
-  pub fn build_index(&mut self) {
-    // Start by making a single index for all items
-    self.index.push(
-      PointIndex {
-        timestamp: Some(Utc::now()),
-        less: 0,
-        more: 0,
-        parent: 0,
-        data: Some(self.points.clone()),
+
+  // Remove the points from `start` to `end` (both inclusive) from the index, one leaf at a time.
+  // Each leaf has all of its points in the range taken out together, and is then merged or
+  // rebalanced once.
+  fn remove_range_from_index(&mut self, start: DateTime<Utc>, end: DateTime<Utc>) {
+    loop {
+      // Merging can move leaves around, so look for the next leaf with points in the range from
+      // the top each time.
+      let mut found = None;
+      let mut next_index = self.lookup_first_in_index(start);
+      while let Some(node_index) = next_index {
+        let data = self.index[node_index].data.as_ref().unwrap();
+        let from = data.partition_point(|p| p.timestamp < start);
+        let to = data.partition_point(|p| p.timestamp <= end);
+        if from < to {
+          found = Some((node_index, from, to));
+          break;
+        }
+        if to < data.len() {
+          break;
+        }
+        next_index = self.get_node_more_than(node_index);
       }
-    );
 
-    // Then, break up that node into smaller nodes
-    self.rebalance_index_node(0);
+      match found {
+        Some((node_index, from, to)) => {
+          self.index[node_index].data.as_mut().unwrap().drain(from..to);
+          self.merge_index_node(node_index);
+        },
+        None => return,
+      }
+    }
+  }
+
+  // Remove a single point from the leaf that holds it, then rebalance the tree around that leaf.
+  fn remove_from_index(&mut self, point: &Point) {
+    // Points that share a timestamp can be spread over a few neighboring leaves, so start at the
+    // first one that could hold the point and work forwards.
+    let mut next_index = self.lookup_first_in_index(point.timestamp);
+    while let Some(node_index) = next_index {
+      let (position, past_timestamp) = match self.index[node_index].data {
+        Some(ref data) => (
+          // Compare values such that a NaN point can still be found.
+          data.iter().position(|p| {
+            p.timestamp == point.timestamp &&
+              (p.value == point.value || (p.value.is_nan() && point.value.is_nan()))
+          }),
+          data.last().is_none_or(|p| p.timestamp > point.timestamp),
+        ),
+        None => (None, true),
+      };
+
+      if let Some(position) = position {
+        if let Some(ref mut data) = self.index[node_index].data {
+          data.remove(position);
+        }
+        self.merge_index_node(node_index);
+        return;
+      }
+      if past_timestamp {
+        return;
+      }
+      next_index = self.get_node_more_than(node_index);
+    }
+  }
+
+  // After something has been removed from the node at `node_index`, make sure it still holds
+  // enough to stay in the tree:
+  //
+  // - If a neighboring sibling can spare enough points (or children), move them over.
+  // - Otherwise, merge the node with that sibling. The parent loses a child, so it may need to be
+  //   merged too.
+  //
+  // A root left with a single child is replaced by that child. Along the way, the summaries of the
+  // node and everything above it are brought up to date.
+  fn merge_index_node(&mut self, node_index: usize) {
+    if node_index == 0 {
+      if !self.index[0].is_leaf() && self.index[0].children.len() == 1 {
+        self.shrink_index();
+      } else {
+        self.summarize_index_node(0);
+      }
+      return;
+    }
+    if self.index[node_index].len() >= self.minimum_length_of_index_node(node_index) {
+      self.summarize_up_from_index_node(node_index);
+      return;
+    }
+
+    // Prefer the sibling to the left, but the first child of a node only has one to the right.
+    let mut parent_index = self.index[node_index].parent;
+    let position = self.index[parent_index].children.iter()
+      .position(|&child| child == node_index).unwrap();
+    let key_position = if position > 0 { position-1 } else { position };
+    let less_index = self.index[parent_index].children[key_position];
+    let more_index = self.index[parent_index].children[key_position+1];
+    let sibling_index = if less_index == node_index { more_index } else { less_index };
+
+    let minimum_length = self.minimum_length_of_index_node(node_index);
+    if self.index[sibling_index].len() + self.index[node_index].len() >= 2 * minimum_length {
+      debug!("Moving items from index node {} to {}", sibling_index, node_index);
+      while self.index[node_index].len() < minimum_length {
+        if sibling_index == less_index {
+          self.move_to_more_index_node(parent_index, key_position);
+        } else {
+          self.move_to_less_index_node(parent_index, key_position);
+        }
+      }
+      self.summarize_index_node(less_index);
+      self.summarize_index_node(more_index);
+      self.summarize_up_from_index_node(parent_index);
+      return;
+    }
+
+    debug!("Merging index node {} into {}", more_index, less_index);
+    let more = mem::replace(&mut self.index[more_index], PointIndex::new_default_value());
+    let separator = self.index[parent_index].keys.remove(key_position);
+    self.index[parent_index].children.remove(key_position+1);
+    match more.data {
+      Some(data) => {
+        if let Some(more_more_index) = more.more {
+          self.index[more_more_index].less = Some(less_index);
+        }
+        let less = &mut self.index[less_index];
+        less.data.as_mut().unwrap().extend(data);
+        less.more = more.more;
+      },
+      None => {
+        for &child_index in &more.children {
+          self.index[child_index].parent = less_index;
+        }
+        let less = &mut self.index[less_index];
+        less.keys.push(separator);
+        less.keys.extend(more.keys);
+        less.children.extend(more.children);
+      },
+    }
+    self.summarize_index_node(less_index);
+
+    // Removing a node can move another node into its slot, so keep track of where the parent is.
+    if self.remove_index_node(more_index) == parent_index {
+      parent_index = more_index;
+    }
+    self.merge_index_node(parent_index);
+  }
+
+  // Move the last point (or child) of `children[key_position]` of the given parent to the start of
+  // `children[key_position+1]`, updating the key between them.
+  fn move_to_more_index_node(&mut self, parent_index: usize, key_position: usize) {
+    let less_index = self.index[parent_index].children[key_position];
+    let more_index = self.index[parent_index].children[key_position+1];
+
+    let new_separator = if self.index[less_index].is_leaf() {
+      let point = self.index[less_index].data.as_mut().unwrap().pop().unwrap();
+      let separator = point.timestamp;
+      self.index[more_index].data.as_mut().unwrap().insert(0, point);
+      separator
+    } else {
+      let separator = self.index[parent_index].keys[key_position];
+      let child_index = self.index[less_index].children.pop().unwrap();
+      let less_key = self.index[less_index].keys.pop().unwrap();
+      let more = &mut self.index[more_index];
+      more.children.insert(0, child_index);
+      more.keys.insert(0, separator);
+      self.index[child_index].parent = more_index;
+      less_key
+    };
+    self.index[parent_index].keys[key_position] = new_separator;
+  }
+
+  // Move the first point (or child) of `children[key_position+1]` of the given parent to the end
+  // of `children[key_position]`, updating the key between them.
+  fn move_to_less_index_node(&mut self, parent_index: usize, key_position: usize) {
+    let less_index = self.index[parent_index].children[key_position];
+    let more_index = self.index[parent_index].children[key_position+1];
+
+    let new_separator = if self.index[more_index].is_leaf() {
+      let point = self.index[more_index].data.as_mut().unwrap().remove(0);
+      self.index[less_index].data.as_mut().unwrap().push(point);
+      self.index[more_index].data.as_ref().unwrap()[0].timestamp
+    } else {
+      let separator = self.index[parent_index].keys[key_position];
+      let child_index = self.index[more_index].children.remove(0);
+      let more_key = self.index[more_index].keys.remove(0);
+      let less = &mut self.index[less_index];
+      less.children.push(child_index);
+      less.keys.push(separator);
+      self.index[child_index].parent = less_index;
+      more_key
+    };
+    self.index[parent_index].keys[key_position] = new_separator;
+  }
+
+  // Remove the node at `node_index` from the index, which must no longer be referenced by any
+  // other node. The last node in the index is moved into its slot, and every link pointing at the
+  // moved node is updated. Returns the index the moved node used to have.
+  fn remove_index_node(&mut self, node_index: usize) -> usize {
+    let last_index = self.index.len()-1;
+    self.index.swap_remove(node_index);
+    if node_index == last_index {
+      return last_index;
+    }
+
+    let moved = self.index[node_index].clone();
+    for child in self.index[moved.parent].children.iter_mut() {
+      if *child == last_index {
+        *child = node_index;
+      }
+    }
+    for &child_index in &moved.children {
+      self.index[child_index].parent = node_index;
+    }
+    if let Some(less_index) = moved.less {
+      self.index[less_index].more = Some(node_index);
+    }
+    if let Some(more_index) = moved.more {
+      self.index[more_index].less = Some(node_index);
+    }
+
+    last_index
   }
 
   // Given a timestamp and a projection, project the chart and return the value at that point using
@@ -119,79 +516,113 @@ impl Chart {
   pub fn get_value_projection(
     &self,
     timestamp: DateTime<Utc>,
-    projection: Option<(&Projection, &mut ProjectionDisposable)>,
+    projection: Option<&Projection>,
+  ) -> Option<f64> {
+    self.get_value_projection_with(timestamp, projection, self.interpolation)
+  }
+
+  // Like `get_value_projection`, but interpolating between points with `interpolation` rather than
+  // the chart's own interpolation.
+  pub fn get_value_projection_with(
+    &self,
+    timestamp: DateTime<Utc>,
+    projection: Option<&Projection>,
+    interpolation: Interpolation,
   ) -> Option<f64> {
     debug!(
-      "CALLING chart.get_value_projection({:?}, {})",
+      "CALLING chart.get_value_projection_with({:?}, {}, {:?})",
       timestamp,
-      if projection.is_some() { "<projection>" } else { "None" }
+      if projection.is_some() { "<projection>" } else { "None" },
+      interpolation,
     );
-    if let Some(node_index) = self.lookup_in_index(timestamp) {
-      debug!("Timestamp {} is in node index {}", timestamp, node_index);
-      let (node, projection) = self.project_index_node(node_index, projection);
-      if let Some(ref node_data) = node.data {
-        if node_data.len() == 0 {
-          debug!("Index node {} is empty, returning None", node_index);
-          return None;
-        } else if timestamp < node_data[0].timestamp {
-          debug!("Need index node LESS than {} in order to interpolate", node_index);
-          // Need to fetch node index less than current node index in order to get item smaller than
-          // current value.
-          if let Some(node_less_index) = self.get_node_less_than(node_index) {
-            let (node_less, projection) = self.project_index_node(node_less_index, projection);
-            if let Some(ref node_less_data) = node_less.data {
-              if node_less_data.len() > 0 {
-                let smaller_value = &node_less_data[node_less_data.len()-1];
-                let larger_value = &node_data[0];
-                let interpolated_value = self.interpolate_between_points(
-                  timestamp,
-                  smaller_value,
-                  larger_value,
-                );
-                return Some(interpolated_value);
-              }
-            }
-          }
-        } else if timestamp > node_data[node_data.len()-1].timestamp {
-          debug!("Need index node MORE than {} in order to interpolate", node_index);
-          // Need to fetch node index more than current node index in order to get item larger than
-          // current value.
-          if let Some(node_more_index) = self.get_node_more_than(node_index) {
-            let (node_more, projection) = self.project_index_node(node_more_index, projection);
-            if let Some(ref node_more_data) = node_more.data {
-              if node_more_data.len() > 0 {
-                let smaller_value = &node_data[node_data.len()-1];
-                let larger_value = &node_more_data[0];
-                let interpolated_value = self.interpolate_between_points(
-                  timestamp,
-                  smaller_value,
-                  larger_value,
-                );
-                return Some(interpolated_value);
-              }
-            }
-          }
-        } else {
-          debug!("No other index nodes needed other than {}", node_index);
-          for point_index in 1..node_data.len()-1 {
-            if timestamp < node_data[point_index].timestamp {
-              let smaller_value = &node_data[point_index];
-              let larger_value = &node_data[point_index+1];
-              return Some(self.interpolate_between_points(timestamp, smaller_value, larger_value));
-            }
-          }
+    let (before, after) = self.with_projection_disposable(projection, |projection| {
+      self.points_around_in_index(timestamp, self.points_needed_around(interpolation), projection)
+    });
+    self.interpolate_around(timestamp, &before, &after, interpolation)
+  }
+
+  // Collect the points on either side of `timestamp` from the index, projecting each leaf along the
+  // way: up to `count` points before `timestamp`, and up to `count` points at or after it. Both
+  // lists are ordered from closest to `timestamp` to furthest away.
+  fn points_around_in_index<'a>(
+    &'a self,
+    timestamp: DateTime<Utc>,
+    count: usize,
+    projection: Option<(&'a Projection, &'a mut ProjectionDisposable)>,
+  ) -> (Vec<Point>, Vec<Point>) {
+    let mut before: Vec<Point> = vec![];
+    let mut after: Vec<Point> = vec![];
+
+    // Start at the first leaf that could hold `timestamp`, so that when several points share a
+    // timestamp the first of them is used, the same as `get_value_vec`. If the projection moves
+    // points in time, that's the leaf holding the points that get moved to `timestamp`.
+    let source_timestamp = match projection {
+      Some((projection, _)) => projection.source_time(timestamp),
+      None => timestamp,
+    };
+    let start_index = match self.lookup_first_in_index(source_timestamp) {
+      Some(start_index) => start_index,
+      None => return (before, after),
+    };
+    debug!("Timestamp {} is in node index {}", timestamp, start_index);
+
+    // Leaves outside of the projection's range have no points in it, so the walks stop there.
+    let range = projection.as_ref().map(|&(projection, _)| {
+      (projection.start_time(), projection.end_time())
+    });
 
-          // For checking the last item, the logic is slightly different.
-          if timestamp <= node_data[node_data.len()-1].timestamp {
-            let smaller_value = &node_data[node_data.len()-2];
-            let larger_value = &node_data[node_data.len()-1];
-            return Some(self.interpolate_between_points(timestamp, smaller_value, larger_value));
+    // Walk forwards through the leaves until there are enough points at or after `timestamp`.
+    let mut projection = projection;
+    let mut next_index = Some(start_index);
+    while let Some(node_index) = next_index {
+      if after.len() >= count {
+        break;
+      }
+      if let (Some((_, end_time)), Some(first)) = (range, self.first_point_in(node_index)) {
+        if first.timestamp > end_time {
+          break;
+        }
+      }
+
+      let node = self.project_index_node(node_index, &mut projection);
+      if let Some(ref data) = node.data {
+        for point in data {
+          if point.timestamp >= timestamp && after.len() < count {
+            after.push(point.clone());
           }
         }
+
+        // The first leaf can also have points before `timestamp`.
+        if node_index == start_index {
+          let earlier_points = data.iter().rev().filter(|p| p.timestamp < timestamp);
+          before.extend(earlier_points.take(count).cloned());
+        }
       }
+      next_index = self.get_node_more_than(node_index);
     }
 
-    None
+    // Then walk backwards until there are enough points before it.
+    let mut next_index = self.get_node_less_than(start_index);
+    while let Some(node_index) = next_index {
+      if before.len() >= count {
+        break;
+      }
+      if let (Some((start_time, _)), Some(last)) = (range, self.last_point_in(node_index)) {
+        if last.timestamp < start_time {
+          break;
+        }
+      }
+      debug!("Need index node LESS than {} in order to interpolate", start_index);
+
+      let node = self.project_index_node(node_index, &mut projection);
+      if let Some(ref data) = node.data {
+        let needed = count - before.len();
+        before.extend(data.iter().rev().take(needed).cloned());
+      }
+      next_index = self.get_node_less_than(node_index);
+    }
+
+    (before, after)
   }
 
   // Given a timestamp, return the value found at that location on the chart.
@@ -199,7 +630,18 @@ impl Chart {
     self.get_value_projection(timestamp, None /* no projection */)
   }
 
-  // Find the location in the index that would contain a node with `timestamp`.
+  // Like `get_value`, but interpolating between points with `interpolation` rather than the
+  // chart's own interpolation.
+  pub fn get_value_with(
+    &self,
+    timestamp: DateTime<Utc>,
+    interpolation: Interpolation,
+  ) -> Option<f64> {
+    self.get_value_projection_with(timestamp, None /* no projection */, interpolation)
+  }
+
+  // Find the leaf in the index that would contain a node with `timestamp`. This is the leaf
+  // holding the last point at or before `timestamp`, or the first leaf if there isn't one.
   pub fn lookup_in_index(&self, timestamp: DateTime<Utc>) -> Option<usize> {
     debug!("CALLING chart.lookup_in_index({:?})", timestamp);
     if self.index.len() == 0 {
@@ -207,102 +649,77 @@ impl Chart {
     }
 
     let mut node_index = 0;
-    loop {
-      match self.index[node_index].timestamp {
-        Some(node_timestamp) => {
-          // At a regular node. Figure out which leg to traverse down.
-          debug!("Looking up regular node {}", node_index);
-          if node_timestamp > timestamp {
-            debug!("Moving in the LESS direction: {} => {}", node_index, self.index[node_index].less);
-            node_index = self.index[node_index].less;
-          } else {
-            debug!("Moving in the MORE direction: {} => {}", node_index, self.index[node_index].more);
-            node_index = self.index[node_index].more;
-          }
-          continue;
-        }
-        None => {
-          // At a leaf.
-          debug!("Reached leaf {}", node_index);
-          break
-        }
-      }
+    while !self.index[node_index].is_leaf() {
+      let node = &self.index[node_index];
+      let position = node.keys.partition_point(|key| *key <= timestamp);
+      debug!("Moving from node {} to child {}", node_index, node.children[position]);
+      node_index = node.children[position];
     }
 
-    debug!("DONE chart.lookup_in_index({:?})", timestamp);
+    debug!("DONE chart.lookup_in_index({:?}) => {}", timestamp, node_index);
     Some(node_index)
   }
 
-  fn get_node_less_than(&self, node_index: usize) -> Option<usize> {
-    let parent_node_index = self.index[node_index].parent;
-    let parent_parent_node_index = self.index[parent_node_index].parent;
-
-    if self.index[parent_node_index].less == node_index {
-      //
-      //          &
-      //    Less / \ More
-      //        /   \
-      //       O     #
-      //      / \   / \
-      //         $ @   \
-      //     end-^ ^-start
-      //
-      // We are at the # (node_index), traversing down to the @.
-      // The node with values less than @ is $. Traversing to it requires knowlege of &
-      // (parent_node_index).
-      //
-      return Some(self.index[self.index[parent_parent_node_index].less].more);
+  // Find the first leaf in the index that could contain a node with `timestamp`. Unlike
+  // `lookup_in_index`, when points sharing a timestamp span several leaves this finds the first of
+  // them.
+  pub fn lookup_first_in_index(&self, timestamp: DateTime<Utc>) -> Option<usize> {
+    if self.index.len() == 0 {
+      panic!("Index is not built, this is a requirement to lookup in the index!");
     }
 
-    if self.index[parent_node_index].more == node_index {
-      return Some(self.index[self.index[node_index].parent].less);
+    let mut node_index = 0;
+    while !self.index[node_index].is_leaf() {
+      let node = &self.index[node_index];
+      node_index = node.children[node.keys.partition_point(|key| *key < timestamp)];
     }
-
-    None
+    Some(node_index)
   }
-  fn get_node_more_than(&self, node_index: usize) -> Option<usize> {
-    let parent_node_index = self.index[node_index].parent;
-    let parent_parent_node_index = self.index[parent_node_index].parent;
 
-    if self.index[parent_node_index].more == node_index {
-      //
-      //          &
-      //    Less / \ More
-      //        /   \
-      //       #     O
-      //      / \   / \
-      //         @ $   \
-      //   start-^ ^-end
-      //
-      // We are at the # (node_index), traversing down to the @.
-      // The node with values more than @ is $. Traversing to it requires knowlege of &
-      // (parent_node_index).
-      //
-      return Some(self.index[self.index[parent_parent_node_index].more].less);
+  // Find the first / last leaf in the index.
+  pub fn first_leaf_in_index(&self) -> usize {
+    let mut node_index = 0;
+    while !self.index[node_index].is_leaf() {
+      node_index = self.index[node_index].children[0];
     }
-
-    if self.index[parent_node_index].less == node_index {
-      return Some(self.index[parent_node_index].more);
+    node_index
+  }
+  pub fn last_leaf_in_index(&self) -> usize {
+    let mut node_index = 0;
+    while !self.index[node_index].is_leaf() {
+      node_index = self.index[node_index].children[self.index[node_index].children.len()-1];
     }
+    node_index
+  }
 
-    None
+  // Return the leaf before / after the leaf at `node_index`. Leaves link directly to their
+  // neighbors, so this works the same no matter how deep the tree is or whether the two leaves
+  // share a parent.
+  fn get_node_less_than(&self, node_index: usize) -> Option<usize> {
+    self.index[node_index].less
+  }
+  fn get_node_more_than(&self, node_index: usize) -> Option<usize> {
+    self.index[node_index].more
+  }
+
+  fn first_point_in(&self, node_index: usize) -> Option<&Point> {
+    self.index[node_index].data.as_ref().and_then(|data| data.first())
+  }
+  fn last_point_in(&self, node_index: usize) -> Option<&Point> {
+    self.index[node_index].data.as_ref().and_then(|data| data.last())
   }
 
   pub fn print_indexes(&self) {
     println!("== START INDEXES ==");
     let mut ct = 0;
     for index in &self.index {
-      if index.timestamp.is_none() {
-        println!("{}\tLEAF\t{:?} => parent:{}", ct, index.timestamp, index.parent);
-        if let Some(ref data) = index.data {
-          for item in data {
-            println!("    - {:?} {:?}", item.timestamp, item.value);
-          }
-        } else {
-          println!("    - None");
+      if let Some(ref data) = index.data {
+        println!("{}\tLEAF\t=> less:{:?} more:{:?} parent:{}", ct, index.less, index.more, index.parent);
+        for item in data {
+          println!("    - {:?} {:?}", item.timestamp, item.value);
         }
       } else {
-        println!("{}\tNODE\t{:?} => less:{} more:{} parent:{}", ct, index.timestamp, index.less, index.more, index.parent);
+        println!("{}\tNODE\t{:?} => children:{:?} parent:{}", ct, index.keys, index.children, index.parent);
       }
       ct += 1;
     }
@@ -313,38 +730,146 @@ impl Chart {
 
 #[cfg(test)]
 mod tests {
-  use chrono::{Utc, TimeZone};
+  use chrono::{Duration, Utc, TimeZone};
+  use chart::aggregation::Summary;
   use chart::chart::Chart;
   use chart::point::Point;
+  use chart::quantile::QuantileSketch;
+
+  // Walk the leaves of the index from first to last, returning all of their data.
+  fn leaf_points(chart: &Chart) -> Vec<Point> {
+    let mut node_index = 0;
+    while !chart.index[node_index].is_leaf() {
+      node_index = chart.index[node_index].children[0];
+    }
+
+    let mut points = vec![];
+    let mut next_index = Some(node_index);
+    while let Some(node_index) = next_index {
+      points.extend(chart.index[node_index].data.clone().unwrap());
+      next_index = chart.index[node_index].more;
+    }
+    points
+  }
+
+  // Check that a node's cached summary matches the points under it. The mean and spread are found
+  // differently depending on how the points were split up, so they only have to be close.
+  fn assert_summary_matches(summary: &Summary, points: &[Point], node_index: usize) {
+    let expected = Summary::from_points(points);
+    assert_eq!(summary.count, expected.count, "node {} has the wrong count", node_index);
+    assert_eq!(summary.min, expected.min, "node {} has the wrong min", node_index);
+    assert_eq!(summary.max, expected.max, "node {} has the wrong max", node_index);
+    assert_eq!(summary.first, expected.first, "node {} has the wrong first", node_index);
+    assert_eq!(summary.last, expected.last, "node {} has the wrong last", node_index);
+    for &(actual, expected) in &[
+      (summary.sum, expected.sum),
+      (summary.mean, expected.mean),
+      (summary.spread, expected.spread),
+    ] {
+      assert!((actual - expected).abs() <= 1e-6 * expected.abs().max(1.0), "node {}", node_index);
+    }
+  }
+
+  // Check every node under `node_index` against the B+tree invariants, returning the depth of the
+  // subtree and its leaves in order.
+  fn check_index_node(
+    chart: &Chart,
+    node_index: usize,
+    seen: &mut Vec<bool>,
+  ) -> (usize, Vec<usize>) {
+    assert!(!seen[node_index], "node {} is referenced more than once", node_index);
+    seen[node_index] = true;
+
+    let node = &chart.index[node_index];
+    if node_index != 0 {
+      let minimum_length = chart.minimum_length_of_index_node(node_index);
+      assert!(node.len() >= minimum_length, "node {} is underfull", node_index);
+    }
+    let capacity = chart.capacity_of_index_node(node_index);
+    assert!(node.len() <= capacity, "node {} is overfull", node_index);
+
+    if node.is_leaf() {
+      assert_summary_matches(&node.summary, node.data.as_ref().unwrap(), node_index);
+      return (1, vec![node_index]);
+    }
+
+    assert_eq!(node.keys.len(), node.children.len() - 1);
+    let mut depth = None;
+    let mut leaves = vec![];
+    for (position, &child_index) in node.children.iter().enumerate() {
+      assert_eq!(chart.index[child_index].parent, node_index);
+      let (child_depth, child_leaves) = check_index_node(chart, child_index, seen);
+      assert_eq!(*depth.get_or_insert(child_depth), child_depth, "leaves are at different depths");
+
+      // Every point under this child has to be between the keys on either side of it.
+      for &leaf_index in &child_leaves {
+        for point in chart.index[leaf_index].data.as_ref().unwrap() {
+          if position > 0 {
+            assert!(point.timestamp >= node.keys[position-1]);
+          }
+          if position < node.keys.len() {
+            assert!(point.timestamp <= node.keys[position]);
+          }
+        }
+      }
+      leaves.extend(child_leaves);
+    }
+
+    let points: Vec<Point> = leaves.iter()
+      .flat_map(|&leaf_index| chart.index[leaf_index].data.clone().unwrap())
+      .collect();
+    assert_summary_matches(&node.summary, &points, node_index);
+    if let Some(sketch) = node.sketch.get() {
+      let mut expected = QuantileSketch::new();
+      for point in &points {
+        expected.add(point.value);
+      }
+      assert_eq!(sketch, &expected, "node {} has the wrong sketch", node_index);
+    }
+    (depth.unwrap() + 1, leaves)
+  }
+
+  // Make sure the index is a valid B+tree that holds exactly the points in `chart.points`, and
+  // return its depth.
+  fn assert_index_consistent(chart: &Chart) -> usize {
+    let mut seen = vec![false; chart.index.len()];
+    let (depth, leaves) = check_index_node(chart, 0, &mut seen);
+    assert!(seen.iter().all(|&seen| seen), "index contains unreachable nodes");
+
+    // The sibling links have to visit the leaves in the same order as walking the tree does.
+    for (position, &leaf_index) in leaves.iter().enumerate() {
+      let leaf = &chart.index[leaf_index];
+      assert_eq!(leaf.less, if position > 0 { Some(leaves[position-1]) } else { None });
+      assert_eq!(leaf.more, leaves.get(position+1).cloned());
+    }
+
+    assert_eq!(leaf_points(chart), chart.points);
+    depth
+  }
 
   #[test]
   fn it_gets_items_via_index() {
-    let mut chart = Chart {
-      max_index_node_capacity: 3,
-      points: vec![
-        Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 10, 0)),
-        Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)),
-        Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 12, 0)),
-        Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 13, 0)),
-        Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 14, 0)),
-        Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 15, 0)),
-        Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 16, 0)),
-        Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 17, 0)),
-        Point::new(4.0, Utc.ymd(2018, 1, 1).and_hms(9, 18, 0)),
-        Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 19, 0)),
-        Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 20, 0)),
-        Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 21, 0)),
-        Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 22, 0)),
-        Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 23, 0)),
-        Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 24, 0)),
-        Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 25, 0)),
-        Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 26, 0)),
-        Point::new(4.0, Utc.ymd(2018, 1, 1).and_hms(9, 27, 0)),
-        Point::new(5.1, Utc.ymd(2018, 1, 1).and_hms(9, 28, 0)),
-      ],
-      index: vec![],
-    };
-    chart.build_index();
+    let chart = Chart::new(vec![
+      Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 10, 0)),
+      Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)),
+      Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 12, 0)),
+      Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 13, 0)),
+      Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 14, 0)),
+      Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 15, 0)),
+      Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 16, 0)),
+      Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 17, 0)),
+      Point::new(4.0, Utc.ymd(2018, 1, 1).and_hms(9, 18, 0)),
+      Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 19, 0)),
+      Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 20, 0)),
+      Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 21, 0)),
+      Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 22, 0)),
+      Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 23, 0)),
+      Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 24, 0)),
+      Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 25, 0)),
+      Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 26, 0)),
+      Point::new(4.0, Utc.ymd(2018, 1, 1).and_hms(9, 27, 0)),
+      Point::new(5.1, Utc.ymd(2018, 1, 1).and_hms(9, 28, 0)),
+    ], 3);
 
     // Exact datapoint somewhere in the middle
     assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 14, 0)), Some(9.0));
@@ -359,14 +884,15 @@ mod tests {
     assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 28, 0)), Some(5.1));
 
     /*
-    11      LEAF    None => 9
+    4       LEAF    => less:Some(3) more:Some(5) parent:9
+        - 2018-01-01T09:19:00Z 5.0
         - 2018-01-01T09:20:00Z 6.0
         - 2018-01-01T09:21:00Z 7.0
+    5       LEAF    => less:Some(4) more:Some(6) parent:9
         - 2018-01-01T09:22:00Z 8.0
-    12      LEAF    None => 9
         - 2018-01-01T09:23:00Z 9.0
         - 2018-01-01T09:24:00Z 1.0
-    13      LEAF    None => 10
+    6       LEAF    => less:Some(5) more:Some(7) parent:10
         - 2018-01-01T09:25:00Z 2.0
         - 2018-01-01T09:26:00Z 3.0
     */
@@ -374,12 +900,15 @@ mod tests {
     // The below tests reference the above index layout:
 
     // In between two nodes
+    assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 21, 15)), Some(7.25));
+
+    // Right after the start of the second index node
     assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 22, 15)), Some(8.25));
 
-    // Right after the start of second index node
+    // Past the middle of the second index node
     assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 23, 15)), Some(7.0));
 
-    // Right between the second and third index node
+    // Right between the second and third index node, which have different parents
     assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 24, 15)), Some(1.25));
 
     // Get value in middle of an index node
@@ -388,32 +917,27 @@ mod tests {
 
   #[test]
   fn it_gets_items_within_projection() {
-    let mut chart = Chart {
-      max_index_node_capacity: 3,
-      points: vec![
-        Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 10, 0)),
-        Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)),
-        Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 12, 0)),
-        Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 13, 0)),
-        Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 14, 0)),
-        Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 15, 0)),
-        Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 16, 0)),
-        Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 17, 0)),
-        Point::new(4.0, Utc.ymd(2018, 1, 1).and_hms(9, 18, 0)),
-        Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 19, 0)),
-        Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 20, 0)),
-        Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 21, 0)),
-        Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 22, 0)),
-        Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 23, 0)),
-        Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 24, 0)),
-        Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 25, 0)),
-        Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 26, 0)),
-        Point::new(4.0, Utc.ymd(2018, 1, 1).and_hms(9, 27, 0)),
-        Point::new(5.1, Utc.ymd(2018, 1, 1).and_hms(9, 28, 0)),
-      ],
-      index: vec![],
-    };
-    chart.build_index();
+    let chart = Chart::new(vec![
+      Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 10, 0)),
+      Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)),
+      Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 12, 0)),
+      Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 13, 0)),
+      Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 14, 0)),
+      Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 15, 0)),
+      Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 16, 0)),
+      Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 17, 0)),
+      Point::new(4.0, Utc.ymd(2018, 1, 1).and_hms(9, 18, 0)),
+      Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 19, 0)),
+      Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 20, 0)),
+      Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 21, 0)),
+      Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 22, 0)),
+      Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 23, 0)),
+      Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 24, 0)),
+      Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 25, 0)),
+      Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 26, 0)),
+      Point::new(4.0, Utc.ymd(2018, 1, 1).and_hms(9, 27, 0)),
+      Point::new(5.1, Utc.ymd(2018, 1, 1).and_hms(9, 28, 0)),
+    ], 3);
 
     // Create a projection without operations
     let projection = chart.new_projection(
@@ -426,8 +950,351 @@ mod tests {
     let value = chart.get_value_projection(Utc.ymd(2018, 1, 1).and_hms(9, 14, 0), Some(&projection));
     assert_eq!(value, Some(9.0));
 
+    // Check datapoints at either edge of the projection are there too
+    let value = chart.get_value_projection(Utc.ymd(2018, 1, 1).and_hms(9, 13, 0), Some(&projection));
+    assert_eq!(value, Some(8.0));
+    let value = chart.get_value_projection(Utc.ymd(2018, 1, 1).and_hms(9, 18, 0), Some(&projection));
+    assert_eq!(value, Some(4.0));
+
     // Check datapoint outside projection is None
     let value = chart.get_value_projection(Utc.ymd(2018, 1, 1).and_hms(9, 0, 0), Some(&projection));
     assert_eq!(value, None);
   }
+
+  #[test]
+  fn it_inserts_points_into_a_built_index() {
+    let mut chart = Chart::new(vec![
+      Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 10, 0)),
+      Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 12, 0)),
+      Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 14, 0)),
+      Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 16, 0)),
+      Point::new(4.0, Utc.ymd(2018, 1, 1).and_hms(9, 18, 0)),
+      Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 20, 0)),
+    ], 3);
+
+    chart.insert(Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 13, 0)));
+    chart.insert(Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)));
+    chart.insert(Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 17, 0)));
+    chart.insert(Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 15, 0)));
+
+    // `points` stays sorted, and the index holds exactly the same points in the same order
+    let timestamps: Vec<_> = chart.points.iter().map(|p| p.timestamp).collect();
+    let mut sorted_timestamps = timestamps.clone();
+    sorted_timestamps.sort();
+    assert_eq!(timestamps, sorted_timestamps);
+    assert_index_consistent(&chart);
+
+    // Inserted points can be read back out, and interpolated against
+    assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 13, 0)), Some(8.0));
+    assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 15, 30)), Some(1.5));
+  }
+
+  #[test]
+  fn it_inserts_points_into_an_index_with_a_single_leaf() {
+    let mut chart = Chart::new(vec![
+      Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 10, 0)),
+      Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)),
+    ], 3);
+    assert_eq!(chart.index.len(), 1);
+
+    chart.insert(Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 12, 0)));
+    chart.insert(Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 13, 0)));
+
+    assert!(chart.index.len() > 1);
+    assert_index_consistent(&chart);
+    assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 12, 30)), Some(7.5));
+  }
+
+  #[test]
+  fn it_removes_points() {
+    let mut chart = Chart::new(vec![
+      Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 10, 0)),
+      Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)),
+      Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 12, 0)),
+      Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 13, 0)),
+      Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 14, 0)),
+      Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 15, 0)),
+      Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 16, 0)),
+      Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 17, 0)),
+    ], 3);
+
+    // Removing a timestamp that isn't in the chart does nothing
+    assert_eq!(chart.remove(Utc.ymd(2018, 1, 1).and_hms(9, 13, 30)), None);
+    assert_eq!(chart.points.len(), 8);
+
+    assert_eq!(
+      chart.remove(Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)),
+      Some(Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)))
+    );
+    assert_index_consistent(&chart);
+
+    // The removed point is interpolated over
+    assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)), Some(6.0));
+    assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 10, 30)), Some(5.5));
+
+    // Remove everything, one point at a time
+    let timestamps: Vec<_> = chart.points.iter().map(|p| p.timestamp).collect();
+    for timestamp in timestamps {
+      assert!(chart.remove(timestamp).is_some());
+      assert_index_consistent(&chart);
+    }
+    assert_eq!(chart.index.len(), 1);
+    assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 13, 0)), None);
+  }
+
+  #[test]
+  fn it_removes_ranges_of_points() {
+    let mut chart = Chart::new(vec![
+      Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 10, 0)),
+      Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)),
+      Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 12, 0)),
+      Point::new(8.0, Utc.ymd(2018, 1, 1).and_hms(9, 13, 0)),
+      Point::new(9.0, Utc.ymd(2018, 1, 1).and_hms(9, 14, 0)),
+      Point::new(1.0, Utc.ymd(2018, 1, 1).and_hms(9, 15, 0)),
+      Point::new(2.0, Utc.ymd(2018, 1, 1).and_hms(9, 16, 0)),
+      Point::new(3.0, Utc.ymd(2018, 1, 1).and_hms(9, 17, 0)),
+      Point::new(4.0, Utc.ymd(2018, 1, 1).and_hms(9, 18, 0)),
+      Point::new(5.0, Utc.ymd(2018, 1, 1).and_hms(9, 19, 0)),
+      Point::new(6.0, Utc.ymd(2018, 1, 1).and_hms(9, 20, 0)),
+      Point::new(7.0, Utc.ymd(2018, 1, 1).and_hms(9, 21, 0)),
+    ], 3);
+    let index_length = chart.index.len();
+
+    let removed = chart.remove_range(
+      Utc.ymd(2018, 1, 1).and_hms(9, 11, 0),
+      Utc.ymd(2018, 1, 1).and_hms(9, 16, 0),
+    );
+    assert_eq!(removed.len(), 6);
+    assert_eq!(removed[0].timestamp, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0));
+    assert_eq!(removed[5].timestamp, Utc.ymd(2018, 1, 1).and_hms(9, 16, 0));
+    assert_eq!(chart.points.len(), 6);
+    assert_index_consistent(&chart);
+
+    // Emptied leaves were merged away
+    assert!(chart.index.len() < index_length);
+
+    // A range with nothing in it removes nothing
+    let removed = chart.remove_range(
+      Utc.ymd(2018, 1, 1).and_hms(9, 12, 0),
+      Utc.ymd(2018, 1, 1).and_hms(9, 15, 0),
+    );
+    assert_eq!(removed, vec![]);
+
+    assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 18, 0)), Some(4.0));
+    assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 20, 30)), Some(6.5));
+  }
+
+  #[test]
+  fn it_removes_small_ranges_of_points_a_leaf_at_a_time() {
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 0, 0);
+    let points = (0..400).map(|i| Point::new(i as f64, start + Duration::seconds(i / 2))).collect();
+    let mut chart = Chart::new(points, 4);
+
+    // Each range is less than a tenth of the chart, and takes most of a few leaves with it
+    for &(from, to) in &[(10, 25), (100, 107), (0, 3), (150, 160), (195, 199), (60, 61)] {
+      let (from, to) = (start + Duration::seconds(from), start + Duration::seconds(to));
+      let removed = chart.remove_range(from, to);
+      assert!(removed.iter().all(|point| point.timestamp >= from && point.timestamp <= to));
+      assert_index_consistent(&chart);
+    }
+    assert_eq!(chart.points.len(), 400 - 2 * (16 + 8 + 4 + 11 + 5 + 2));
+    assert_eq!(chart.get_value(start + Duration::seconds(30)), Some(60.0));
+  }
+
+  // A simple linear congruential generator, so tests can shuffle things around repeatably.
+  fn pseudo_random(seed: &mut u64) -> u64 {
+    *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    *seed >> 33
+  }
+
+  #[test]
+  fn it_keeps_the_index_balanced_for_bursty_data() {
+    // Bursts of a hundred points a millisecond apart, with an hour between each burst
+    let mut points = vec![];
+    for burst in 0..50 {
+      let burst_start = Utc.ymd(2018, 1, 1).and_hms(0, 0, 0) + Duration::hours(burst);
+      for offset in 0..100 {
+        points.push(Point::new(offset as f64, burst_start + Duration::milliseconds(offset)));
+      }
+    }
+
+    let chart = Chart::new(points, 4);
+
+    // 5000 points in leaves of at least two, with at least two children per inner node
+    let depth = assert_index_consistent(&chart);
+    assert!(depth <= 12, "index is {} levels deep", depth);
+
+    // The first point of every burst can be found
+    for burst in 0..50 {
+      let burst_start = Utc.ymd(2018, 1, 1).and_hms(0, 0, 0) + Duration::hours(burst);
+      let node_index = chart.lookup_in_index(burst_start).unwrap();
+      let data = chart.index[node_index].data.as_ref().unwrap();
+      assert!(data.iter().any(|point| point.timestamp == burst_start));
+    }
+  }
+
+  #[test]
+  fn it_keeps_the_index_balanced_through_inserts_and_removals() {
+    let mut chart = Chart::new(vec![], 3);
+
+    let mut seed = 42;
+    let mut timestamps = vec![];
+    for _ in 0..1000 {
+      // Only a few hundred distinct timestamps, so there are plenty of duplicates
+      let timestamp = Utc.ymd(2018, 1, 1).and_hms(0, 0, 0) +
+        Duration::seconds((pseudo_random(&mut seed) % 300) as i64);
+      chart.insert(Point::new(pseudo_random(&mut seed) as f64, timestamp));
+      timestamps.push(timestamp);
+    }
+    let depth = assert_index_consistent(&chart);
+    assert!(depth <= 10, "index is {} levels deep", depth);
+
+    for (count, timestamp) in timestamps.iter().enumerate() {
+      assert!(chart.remove(*timestamp).is_some());
+      if count % 50 == 0 {
+        assert_index_consistent(&chart);
+      }
+    }
+    assert_index_consistent(&chart);
+    assert_eq!(chart.points.len(), 0);
+    assert_eq!(chart.index.len(), 1);
+  }
+
+  // Build a chart of `length` points that are irregularly spaced (anywhere from ten milliseconds to
+  // ten seconds apart), with values all over the place.
+  fn irregular_chart(length: usize, max_index_node_capacity: usize, seed: &mut u64) -> Chart {
+    let mut points = vec![];
+    let mut timestamp = Utc.ymd(2018, 1, 1).and_hms(0, 0, 0);
+    for _ in 0..length {
+      let gap_ms = 10 * (1 + pseudo_random(seed) % 1000);
+      timestamp = timestamp + Duration::milliseconds(gap_ms as i64);
+      points.push(Point::new((pseudo_random(seed) % 1000) as f64 - 500.0, timestamp));
+    }
+
+    Chart::new(points, max_index_node_capacity)
+  }
+
+  // Compare `get_value` against `get_value_vec` on every point, halfway between every pair of
+  // points, and just either side of every point.
+  fn assert_matches_get_value_vec(chart: &Chart) {
+    for pair in chart.points.windows(2) {
+      let (before, after) = (&pair[0], &pair[1]);
+      let halfway = before.timestamp + (after.timestamp - before.timestamp) / 2;
+      for &timestamp in &[
+        after.timestamp,
+        halfway,
+        before.timestamp + Duration::nanoseconds(1),
+        after.timestamp - Duration::nanoseconds(1),
+      ] {
+        assert_eq!(
+          chart.get_value(timestamp), chart.get_value_vec(timestamp),
+          "mismatch at {:?} (capacity {})", timestamp, chart.max_index_node_capacity,
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn it_interpolates_across_leaves_in_deep_trees() {
+    let mut seed = 7;
+    for max_index_node_capacity in 1..6 {
+      let chart = irregular_chart(2000, max_index_node_capacity, &mut seed);
+      let depth = assert_index_consistent(&chart);
+      assert!(depth > 3, "index is only {} levels deep", depth);
+      assert_matches_get_value_vec(&chart);
+
+      // Outside of the chart there's nothing to interpolate between
+      let first = chart.points[0].timestamp;
+      let last = chart.points[chart.points.len()-1].timestamp;
+      assert_eq!(chart.get_value(first), Some(chart.points[0].value));
+      assert_eq!(chart.get_value(first - Duration::seconds(1)), None);
+      assert_eq!(chart.get_value(last + Duration::seconds(1)), None);
+    }
+  }
+
+  #[test]
+  fn it_interpolates_across_leaves_after_inserts_and_removals() {
+    let mut seed = 11;
+    for max_index_node_capacity in 2..5 {
+      let mut chart = irregular_chart(1500, max_index_node_capacity, &mut seed);
+
+      // Remove a third of the points, then put some back in different places
+      for _ in 0..500 {
+        let position = (pseudo_random(&mut seed) as usize) % chart.points.len();
+        let timestamp = chart.points[position].timestamp;
+        chart.remove(timestamp);
+      }
+      for _ in 0..250 {
+        let position = (pseudo_random(&mut seed) as usize) % (chart.points.len()-1);
+        let timestamp = chart.points[position].timestamp + Duration::milliseconds(5);
+        if chart.points.iter().any(|point| point.timestamp == timestamp) {
+          continue;
+        }
+        chart.insert(Point::new((pseudo_random(&mut seed) % 100) as f64, timestamp));
+      }
+
+      assert_index_consistent(&chart);
+      assert_matches_get_value_vec(&chart);
+    }
+  }
+
+  #[test]
+  fn it_interpolates_with_sub_second_precision() {
+    // One second of readings at 100 Hz
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 10, 0);
+    let points: Vec<Point> = (0..100)
+      .map(|i| Point::new(i as f64, start + Duration::milliseconds(10 * i)))
+      .collect();
+    let mut chart = Chart::new(points, 4);
+    assert_index_consistent(&chart);
+
+    assert_eq!(chart.get_value(start + Duration::milliseconds(420)), Some(42.0));
+    assert_eq!(chart.get_value(start + Duration::milliseconds(425)), Some(42.5));
+    assert_eq!(chart.get_value(start + Duration::microseconds(422500)), Some(42.25));
+    assert_eq!(chart.get_value_vec(start + Duration::microseconds(422500)), Some(42.25));
+
+    // Points less than a millisecond apart can still be interpolated between
+    chart.insert(Point::new(1000.0, start + Duration::microseconds(990100)));
+    assert_eq!(chart.get_value(start + Duration::microseconds(990050)), Some(549.5));
+    let value = chart.get_value(start + Duration::nanoseconds(990000001)).unwrap();
+    assert!(value > 99.0 && value < 99.01);
+  }
+
+  #[test]
+  fn it_handles_leaves_where_every_point_shares_a_timestamp() {
+    let timestamp = Utc.ymd(2018, 1, 1).and_hms(9, 10, 0);
+    let mut points = vec![Point::new(-10.0, Utc.ymd(2018, 1, 1).and_hms(9, 9, 0))];
+    for value in 0..20 {
+      points.push(Point::new(value as f64, timestamp));
+    }
+    points.push(Point::new(100.0, Utc.ymd(2018, 1, 1).and_hms(9, 11, 0)));
+
+    let mut chart = Chart::new(points, 3);
+    assert_index_consistent(&chart);
+
+    // The first point at a timestamp is used for that timestamp, and the last point at a timestamp
+    // is used for interpolating after it.
+    for &(timestamp, value) in &[
+      (Utc.ymd(2018, 1, 1).and_hms(9, 10, 0), 0.0),
+      (Utc.ymd(2018, 1, 1).and_hms(9, 9, 30), -5.0),
+      (Utc.ymd(2018, 1, 1).and_hms(9, 10, 30), 59.5),
+    ] {
+      assert_eq!(chart.get_value(timestamp), Some(value));
+      assert_eq!(chart.get_value_vec(timestamp), Some(value));
+    }
+
+    // Points with the same timestamp keep being added after the existing ones
+    chart.insert(Point::new(20.0, timestamp));
+    chart.insert(Point::new(21.0, timestamp));
+    assert_index_consistent(&chart);
+    assert_eq!(chart.get_value(Utc.ymd(2018, 1, 1).and_hms(9, 10, 30)), Some(60.5));
+
+    // ... and are removed from the front
+    for value in 0..22 {
+      assert_eq!(chart.remove(timestamp), Some(Point::new(value as f64, timestamp)));
+      assert_index_consistent(&chart);
+    }
+    assert_eq!(chart.remove(timestamp), None);
+    assert_eq!(chart.get_value(timestamp), Some(45.0));
+  }
 }
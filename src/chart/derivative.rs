@@ -0,0 +1,228 @@
+extern crate chrono;
+use chrono::{DateTime, Duration, Utc};
+
+use std::ops::Bound;
+
+use chart::chart::{Chart, nanoseconds_in, seconds_between};
+use chart::point::Point;
+
+// How much a counter went up from `previous` to `next`. Counters only ever go up, so a drop means
+// the counter was reset to zero in between, and it went up by its whole new value since then.
+fn counter_increase(previous: &Point, next: &Point) -> f64 {
+  if next.value < previous.value {
+    next.value
+  } else {
+    next.value - previous.value
+  }
+}
+
+// Given the points of a counter from `start` to `end`, work out how much it went up over that
+// whole window, the same way Prometheus does. The points rarely sit right on the edges of the
+// window, so the increase between the first and last point is scaled up to cover the time out to
+// either edge, unless that edge is further away than the points usually are from each other (the
+// series starts or stops in the window), in which case only half the usual gap is added. The
+// counter is never extrapolated back to below zero.
+//
+// `increase` is the increase from the first point to the last, with resets accounted for.
+fn extrapolated_increase(
+  start: DateTime<Utc>,
+  end: DateTime<Utc>,
+  first: &Point,
+  last: &Point,
+  count: usize,
+  increase: f64,
+) -> Option<f64> {
+  if count < 2 {
+    return None;
+  }
+  let sampled_seconds = seconds_between(first.timestamp, last.timestamp);
+  if sampled_seconds <= 0.0 {
+    return None;
+  }
+
+  let mut seconds_to_start = seconds_between(start, first.timestamp);
+  let seconds_to_end = seconds_between(last.timestamp, end);
+  if increase > 0.0 && first.value >= 0.0 {
+    let seconds_to_zero = sampled_seconds * (first.value / increase);
+    if seconds_to_zero < seconds_to_start {
+      seconds_to_start = seconds_to_zero;
+    }
+  }
+
+  let average_gap = sampled_seconds / (count - 1) as f64;
+  let threshold = average_gap * 1.1;
+  let mut extrapolated_seconds = sampled_seconds;
+  for &seconds_to_edge in &[seconds_to_start, seconds_to_end] {
+    extrapolated_seconds += if seconds_to_edge < threshold {
+      seconds_to_edge
+    } else {
+      average_gap / 2.0
+    };
+  }
+  Some(increase * extrapolated_seconds / sampled_seconds)
+}
+
+impl Chart {
+  // A chart of how fast this chart changes, in value per second. Each point is the slope of the
+  // line from the point before it. Points that share a timestamp with the one before them are
+  // skipped. This is meant for gauges; use `rate` for counters.
+  pub fn derivative(&self) -> Chart {
+    debug!("CALLING chart.derivative()");
+    let points = self.points.windows(2)
+      .filter_map(|pair| {
+        let (previous, next) = (&pair[0], &pair[1]);
+        let seconds = seconds_between(previous.timestamp, next.timestamp);
+        if seconds > 0.0 {
+          Some(Point::new((next.value - previous.value) / seconds, next.timestamp))
+        } else {
+          None
+        }
+      })
+      .collect();
+    self.with_points(points)
+  }
+
+  // A chart of how fast this counter goes up, in value per second, over the `window` leading up to
+  // each of its points (not including the start of the window). Drops are treated as counter
+  // resets, so the rate is never negative. Points without at least one other point in their
+  // window are left out.
+  pub fn rate(&self, window: Duration) -> Chart {
+    debug!("CALLING chart.rate({:?})", window);
+    let window_seconds = nanoseconds_in(window) / 1_000_000_000.0;
+    if window_seconds <= 0.0 {
+      panic!("Rates need a window longer than zero, got {:?}!", window);
+    }
+
+    // How much the counter has gone up by each point since the first one, so that the increase
+    // between any two points is a subtraction.
+    let mut total_increase = Vec::with_capacity(self.points.len());
+    for (position, point) in self.points.iter().enumerate() {
+      total_increase.push(match position {
+        0 => 0.0,
+        _ => total_increase[position-1] + counter_increase(&self.points[position-1], point),
+      });
+    }
+
+    let mut points = vec![];
+    let mut first = 0;
+    for (last, point) in self.points.iter().enumerate() {
+      let start = point.timestamp - window;
+      while self.points[first].timestamp <= start {
+        first += 1;
+      }
+
+      let increase = extrapolated_increase(
+        start,
+        point.timestamp,
+        &self.points[first],
+        point,
+        last - first + 1,
+        total_increase[last] - total_increase[first],
+      );
+      if let Some(increase) = increase {
+        points.push(Point::new(increase / window_seconds, point.timestamp));
+      }
+    }
+    self.with_points(points)
+  }
+
+  // How much this counter went up from `start` to `end` (both inclusive), treating drops as
+  // counter resets, and extrapolating out to the edges of the window. There needs to be at least
+  // two points in the window.
+  pub fn increase(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Option<f64> {
+    debug!("CALLING chart.increase({:?}, {:?})", start, end);
+    let mut points = self.range(Bound::Included(start), Bound::Included(end));
+    let first = points.next()?;
+
+    let (mut last, mut count, mut increase) = (first, 1, 0.0);
+    for point in points {
+      increase += counter_increase(last, point);
+      last = point;
+      count += 1;
+    }
+    extrapolated_increase(start, end, first, last, count, increase)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+  use chart::point::Point;
+  use chart::test_helpers::{at, chart_with_a_point_every, chart_with_values};
+
+  #[test]
+  fn it_finds_the_derivative_of_a_chart() {
+    let chart = chart_with_values(&[(0.0, 0), (20.0, 10), (20.0, 30), (0.0, 40), (5.0, 40)], 3);
+    let derivative = chart.derivative();
+    assert_eq!(derivative.points, vec![
+      Point::new(2.0, at(10)),
+      Point::new(0.0, at(30)),
+      Point::new(-2.0, at(40)),
+    ]);
+    assert_eq!(derivative.get_value(at(20)), Some(1.0));
+  }
+
+  #[test]
+  fn it_finds_the_increase_of_a_counter_across_resets() {
+    let chart = chart_with_values(&[
+      (0.0, 0), (10.0, 10), (20.0, 20), (30.0, 30), (5.0, 40), (15.0, 50),
+    ], 3);
+    assert_eq!(chart.increase(at(0), at(50)), Some(45.0));
+
+    // Extrapolated out to the edges of the window
+    let increase = chart.increase(at(5), at(45)).unwrap();
+    assert!((increase - 25.0 * 40.0 / 30.0).abs() < 1e-9);
+    let increase = chart.increase(at(5), at(30)).unwrap();
+    assert!((increase - 20.0 * 25.0 / 20.0).abs() < 1e-9);
+
+    // But not back past zero
+    assert_eq!(chart.increase(at(-5), at(20)), Some(20.0));
+
+    // Or much further than the points usually are from each other
+    let increase = chart.increase(at(20), at(100)).unwrap();
+    assert!((increase - 25.0 * 35.0 / 30.0).abs() < 1e-9);
+
+    assert_eq!(chart.increase(at(12), at(18)), None);
+    assert_eq!(chart.increase(at(10), at(10)), None);
+  }
+
+  #[test]
+  fn it_finds_the_rate_of_a_counter() {
+    // Going up by one a second, reset to zero at 200 seconds
+    let mut values: Vec<(f64, i64)> = (0..20).map(|i| (i as f64 * 10.0, i * 10)).collect();
+    values.extend((20..40).map(|i| ((i - 20) as f64 * 10.0, i * 10)));
+    let chart = chart_with_values(&values, 3);
+
+    let rate = chart.rate(Duration::seconds(30));
+    assert_eq!(rate.points.len(), 39);
+    for point in &rate.points[2..] {
+      assert!(point.value >= 0.0, "negative rate at {:?}", point.timestamp);
+      if point.timestamp < at(200) || point.timestamp > at(220) {
+        assert!((point.value - 1.0).abs() < 1e-9, "{:?}", point);
+      }
+    }
+
+    // Across the reset, the counter only seems to have gone up by ten
+    assert_eq!(rate.get_value(at(210)), Some(0.5));
+
+    // Just after it, the increase is extrapolated back to zero but no further
+    assert_eq!(rate.get_value(at(220)), Some(20.0 / 30.0));
+  }
+
+  #[test]
+  fn it_finds_the_rate_over_windows_shorter_than_a_millisecond() {
+    // Going up by one every one and a half milliseconds
+    let values = (0..20).map(|i| i as f64);
+    let chart = chart_with_a_point_every(Duration::microseconds(1500), values, 3);
+
+    let rate = chart.rate(Duration::microseconds(4500));
+    for point in &rate.points[3..] {
+      assert!((point.value - 1.0 / 0.0015).abs() < 1e-6, "{:?}", point);
+    }
+
+    // A window shorter than a millisecond still works
+    let rate = chart.rate(Duration::microseconds(900));
+    assert_eq!(rate.points.len(), 0);
+  }
+}
@@ -0,0 +1,211 @@
+extern crate chrono;
+use chrono::{DateTime, Utc};
+
+use std::cmp;
+use std::ops::Bound;
+
+use chart::chart::{Chart, seconds_between};
+use chart::point::Point;
+
+impl Chart {
+  // Pick `target_points` of the points from `start` to `end` (both inclusive) that keep the shape
+  // of the chart when it's drawn, using Largest-Triangle-Three-Buckets. The first and last points
+  // are always kept. The points in between are split into buckets, and from each bucket the point
+  // that makes the largest triangle with the point picked from the bucket before and the average of
+  // the bucket after is picked, which favors peaks and troughs. If there are no more than
+  // `target_points` points, they're all returned.
+  pub fn downsample_lttb(
+    &self,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    target_points: usize,
+  ) -> Vec<Point> {
+    debug!("CALLING chart.downsample_lttb({:?}, {:?}, {})", start, end, target_points);
+    let points: Vec<Point> = self.range(Bound::Included(start), Bound::Included(end))
+      .cloned()
+      .collect();
+    if points.len() <= target_points {
+      return points;
+    }
+    match target_points {
+      0 => return vec![],
+      1 => return vec![points[0].clone()],
+      2 => return vec![points[0].clone(), points[points.len()-1].clone()],
+      _ => {},
+    }
+
+    // Measure time in seconds from the first point, so that triangle areas are in reasonable units.
+    let first_timestamp = points[0].timestamp;
+    let x = |point: &Point| seconds_between(first_timestamp, point.timestamp);
+
+    // The points in between the first and last are split into `target_points - 2` buckets.
+    let inner_points = points.len() - 2;
+    let bucket_start = |bucket: usize| bucket * inner_points / (target_points - 2) + 1;
+
+    let mut sampled = vec![points[0].clone()];
+    let mut previous = 0;
+    for bucket in 0..target_points-2 {
+      // The average of the next bucket, or the last point for the last bucket.
+      let next_end = cmp::min(bucket_start(bucket+2), points.len());
+      let next = &points[bucket_start(bucket+1)..next_end];
+      let next_x = next.iter().map(x).sum::<f64>() / next.len() as f64;
+      let next_y = next.iter().map(|point| point.value).sum::<f64>() / next.len() as f64;
+
+      let (previous_x, previous_y) = (x(&points[previous]), points[previous].value);
+      let mut largest_area = -1.0;
+      let start = bucket_start(bucket);
+      for (offset, point) in points[start..bucket_start(bucket+1)].iter().enumerate() {
+        let area = ((previous_x - next_x) * (point.value - previous_y) -
+          (previous_x - x(point)) * (next_y - previous_y)).abs();
+        if area > largest_area {
+          largest_area = area;
+          previous = start + offset;
+        }
+      }
+      sampled.push(points[previous].clone());
+    }
+    sampled.push(points[points.len()-1].clone());
+    sampled
+  }
+
+  // Split the time from `start` to `end` (both inclusive) into `buckets` equally long buckets (say,
+  // one per pixel across a panel), and keep the first, last, smallest and largest point in each
+  // (M4). Drawing lines through what's left looks exactly the same as drawing every point. The
+  // points are returned in timestamp order, with each point at most once.
+  pub fn downsample_m4(
+    &self,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    buckets: usize,
+  ) -> Vec<Point> {
+    debug!("CALLING chart.downsample_m4({:?}, {:?}, {})", start, end, buckets);
+    let mut sampled = vec![];
+    if buckets == 0 {
+      return sampled;
+    }
+
+    let seconds = seconds_between(start, end);
+    let mut bucket_points: Vec<&Point> = vec![];
+    let mut current_bucket = None;
+    for point in self.range(Bound::Included(start), Bound::Included(end)) {
+      let bucket = if seconds > 0.0 {
+        let position = seconds_between(start, point.timestamp) / seconds * buckets as f64;
+        cmp::min(position as usize, buckets - 1)
+      } else {
+        0
+      };
+      if current_bucket != Some(bucket) {
+        keep_m4_points(&bucket_points, &mut sampled);
+        bucket_points.clear();
+        current_bucket = Some(bucket);
+      }
+      bucket_points.push(point);
+    }
+    keep_m4_points(&bucket_points, &mut sampled);
+    sampled
+  }
+}
+
+// Add the first, last, smallest and largest of `points` to `sampled`, in timestamp order.
+fn keep_m4_points(points: &[&Point], sampled: &mut Vec<Point>) {
+  if points.is_empty() {
+    return;
+  }
+
+  let mut smallest = 0;
+  let mut largest = 0;
+  for (index, point) in points.iter().enumerate() {
+    if point.value < points[smallest].value {
+      smallest = index;
+    }
+    if point.value > points[largest].value {
+      largest = index;
+    }
+  }
+
+  let mut kept = vec![0, smallest, largest, points.len()-1];
+  kept.sort();
+  kept.dedup();
+  sampled.extend(kept.into_iter().map(|index| points[index].clone()));
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+  use chart::chart::Chart;
+  use chart::point::Point;
+  use chart::test_helpers::{at, chart_with_a_point_every};
+
+  // A chart with a point every second, following a slow wave with a few spikes.
+  fn wavy_chart(length: i64) -> Chart {
+    let values = (0..length).map(|seconds| {
+      let spike = match seconds {
+        250 => 100.0,
+        700 => -100.0,
+        _ => 0.0,
+      };
+      (seconds as f64 / 50.0).sin() + spike
+    });
+    chart_with_a_point_every(Duration::seconds(1), values, 8)
+  }
+
+  #[test]
+  fn it_downsamples_with_largest_triangle_three_buckets() {
+    let chart = wavy_chart(1000);
+    let sampled = chart.downsample_lttb(at(0), at(999), 50);
+    assert_eq!(sampled.len(), 50);
+    assert_eq!(sampled[0], chart.points[0]);
+    assert_eq!(sampled[49], chart.points[999]);
+    assert!(sampled.windows(2).all(|pair| pair[0].timestamp < pair[1].timestamp));
+
+    // The spikes stand out, so they're kept
+    assert!(sampled.iter().any(|point| point.value > 99.0));
+    assert!(sampled.iter().any(|point| point.value < -99.0));
+
+    // Only the points in the window are used
+    let sampled = chart.downsample_lttb(at(100), at(199), 10);
+    assert_eq!(sampled.len(), 10);
+    assert_eq!(sampled[0].timestamp, at(100));
+    assert_eq!(sampled[9].timestamp, at(199));
+
+    assert_eq!(chart.downsample_lttb(at(100), at(104), 10).len(), 5);
+    assert_eq!(chart.downsample_lttb(at(100), at(199), 2), vec![
+      chart.points[100].clone(),
+      chart.points[199].clone(),
+    ]);
+  }
+
+  #[test]
+  fn it_downsamples_with_m4() {
+    let chart = wavy_chart(1000);
+    let sampled = chart.downsample_m4(at(0), at(999), 100);
+    assert!(sampled.len() <= 400);
+    assert!(sampled.windows(2).all(|pair| pair[0].timestamp < pair[1].timestamp));
+    assert_eq!(sampled[0], chart.points[0]);
+    assert_eq!(sampled[sampled.len()-1], chart.points[999]);
+    assert!(sampled.iter().any(|point| point.value > 99.0));
+    assert!(sampled.iter().any(|point| point.value < -99.0));
+
+    // Each bucket keeps its first, last, smallest and largest point
+    for bucket in 0..100 {
+      let (bucket_start, bucket_end) = (at(bucket * 10), at(bucket * 10 + 10));
+      let in_bucket: Vec<&Point> = chart.points.iter()
+        .filter(|point| point.timestamp >= bucket_start && point.timestamp < bucket_end)
+        .collect();
+      let kept: Vec<&Point> = sampled.iter()
+        .filter(|point| point.timestamp >= bucket_start && point.timestamp < bucket_end)
+        .collect();
+      assert!(kept.len() <= 4);
+      assert_eq!(kept[0], in_bucket[0]);
+      assert_eq!(kept[kept.len()-1], in_bucket[in_bucket.len()-1]);
+
+      let smallest = in_bucket.iter().map(|point| point.value).fold(f64::INFINITY, f64::min);
+      let largest = in_bucket.iter().map(|point| point.value).fold(f64::NEG_INFINITY, f64::max);
+      assert!(kept.iter().any(|point| point.value == smallest));
+      assert!(kept.iter().any(|point| point.value == largest));
+    }
+
+    assert_eq!(chart.downsample_m4(at(2000), at(3000), 100), vec![]);
+  }
+}
@@ -0,0 +1,187 @@
+extern crate chrono;
+use chrono::{DateTime, Duration, Utc};
+
+use chart::chart::{Chart, duration_in_nanoseconds};
+use chart::point::Point;
+
+// What a chart's value is before its first point or after its last point.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[derive(Default)]
+pub enum Extrapolation {
+  // There's no value.
+  #[default]
+  None,
+
+  // Keep the value of the closest point.
+  Hold,
+
+  // Continue the line through the two closest points. With only one point, that point's value is
+  // held instead.
+  Linear,
+}
+
+// How a chart deals with the places it doesn't have data: long gaps between points, and the time
+// before its first point and after its last. The default never makes up a value: there's no limit
+// on gaps, and no value outside of the chart.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct GapPolicy {
+  // The longest time between two points that will be interpolated over. In a longer gap,
+  // `gap_value` is used instead.
+  pub max_gap: Option<Duration>,
+
+  // The value in gaps longer than `max_gap`, if any.
+  pub gap_value: Option<f64>,
+
+  pub before_start: Extrapolation,
+  pub after_end: Extrapolation,
+}
+
+impl GapPolicy {
+  // How many points on one side of a timestamp are needed to extrapolate a value there.
+  pub fn points_needed(&self) -> usize {
+    if self.before_start == Extrapolation::Linear || self.after_end == Extrapolation::Linear {
+      2
+    } else {
+      1
+    }
+  }
+
+  // Whether `previous` and `next` are too far apart to interpolate between.
+  pub fn is_gap(&self, previous: &Point, next: &Point) -> bool {
+    match self.max_gap {
+      Some(max_gap) => next.timestamp.signed_duration_since(previous.timestamp) > max_gap,
+      None => false,
+    }
+  }
+}
+
+// Extend the line from `first` through `second` out to `timestamp`. The two points can be in either
+// order.
+fn linear_extrapolation(timestamp: DateTime<Utc>, first: &Point, second: &Point) -> f64 {
+  let time_between_points_ns = duration_in_nanoseconds(first.timestamp, second.timestamp);
+  if time_between_points_ns == 0.0 {
+    return second.value;
+  }
+
+  let time_to_timestamp_ns = duration_in_nanoseconds(first.timestamp, timestamp);
+  first.value + (second.value - first.value) * (time_to_timestamp_ns / time_between_points_ns)
+}
+
+impl Chart {
+  // Find the value at `timestamp` when it's outside of the chart, from the points on one side
+  // of it. `points` are ordered from closest to `timestamp` to furthest away.
+  pub fn extrapolate(
+    &self,
+    timestamp: DateTime<Utc>,
+    points: &[Point],
+    extrapolation: Extrapolation,
+  ) -> Option<f64> {
+    let closest = points.first()?;
+
+    match (extrapolation, points.get(1)) {
+      (Extrapolation::None, _) => None,
+      (Extrapolation::Hold, _) | (Extrapolation::Linear, None) => Some(closest.value),
+      (Extrapolation::Linear, Some(second_closest)) => {
+        Some(linear_extrapolation(timestamp, second_closest, closest))
+      },
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::{Duration, Utc, TimeZone};
+  use chart::chart::Chart;
+  use chart::gap_policy::{Extrapolation, GapPolicy};
+  use chart::test_helpers::chart_with_values;
+
+  // Points at 9:10, 9:11, 9:12, 10:00 and 10:01.
+  fn chart_with_gap_policy(gap_policy: GapPolicy) -> Chart {
+    let values = [(1.0, 600), (2.0, 660), (4.0, 720), (8.0, 3600), (6.0, 3660)];
+    let mut chart = chart_with_values(&values, 2);
+    chart.gap_policy = gap_policy;
+    chart
+  }
+
+  // Check that both ways of looking up values agree with `value` at `timestamp`.
+  fn assert_value(chart: &Chart, timestamp: (u32, u32, u32), value: Option<f64>) {
+    let timestamp = Utc.ymd(2018, 1, 1).and_hms(timestamp.0, timestamp.1, timestamp.2);
+    assert_eq!(chart.get_value(timestamp), value, "get_value at {:?}", timestamp);
+    assert_eq!(chart.get_value_vec(timestamp), value, "get_value_vec at {:?}", timestamp);
+  }
+
+  #[test]
+  fn it_has_no_values_outside_of_the_chart_by_default() {
+    let chart = chart_with_gap_policy(GapPolicy::default());
+    assert_value(&chart, (9, 9, 0), None);
+    assert_value(&chart, (9, 10, 0), Some(1.0));
+    assert_value(&chart, (10, 1, 0), Some(6.0));
+    assert_value(&chart, (10, 2, 0), None);
+
+    // No matter how long the gap, it's interpolated over
+    assert_value(&chart, (9, 36, 0), Some(6.0));
+  }
+
+  #[test]
+  fn it_extrapolates_before_and_after_the_chart() {
+    let chart = chart_with_gap_policy(GapPolicy {
+      before_start: Extrapolation::Hold,
+      after_end: Extrapolation::Hold,
+      ..GapPolicy::default()
+    });
+    assert_value(&chart, (9, 0, 0), Some(1.0));
+    assert_value(&chart, (11, 0, 0), Some(6.0));
+
+    let chart = chart_with_gap_policy(GapPolicy {
+      before_start: Extrapolation::Linear,
+      after_end: Extrapolation::Linear,
+      ..GapPolicy::default()
+    });
+    assert_value(&chart, (9, 9, 0), Some(0.0));
+    assert_value(&chart, (9, 8, 30), Some(-0.5));
+    assert_value(&chart, (10, 2, 0), Some(4.0));
+    assert_value(&chart, (10, 4, 0), Some(0.0));
+  }
+
+  #[test]
+  fn it_extrapolates_a_single_point_chart_by_holding_it() {
+    let mut chart = chart_with_values(&[(3.0, 600)], 2);
+    chart.gap_policy = GapPolicy {
+      before_start: Extrapolation::Linear,
+      after_end: Extrapolation::Linear,
+      ..GapPolicy::default()
+    };
+    assert_value(&chart, (9, 0, 0), Some(3.0));
+    assert_value(&chart, (9, 20, 0), Some(3.0));
+  }
+
+  #[test]
+  fn it_fills_gaps_longer_than_the_max_gap() {
+    let chart = chart_with_gap_policy(GapPolicy {
+      max_gap: Some(Duration::minutes(5)),
+      ..GapPolicy::default()
+    });
+    assert_value(&chart, (9, 11, 30), Some(3.0));
+    assert_value(&chart, (9, 12, 1), None);
+    assert_value(&chart, (9, 59, 59), None);
+
+    // Points on either side of the gap still have their own values
+    assert_value(&chart, (9, 12, 0), Some(4.0));
+    assert_value(&chart, (10, 0, 0), Some(8.0));
+
+    let chart = chart_with_gap_policy(GapPolicy {
+      max_gap: Some(Duration::minutes(5)),
+      gap_value: Some(0.0),
+      ..GapPolicy::default()
+    });
+    assert_value(&chart, (9, 36, 0), Some(0.0));
+    assert_value(&chart, (10, 0, 30), Some(7.0));
+  }
+}
@@ -0,0 +1,231 @@
+extern crate chrono;
+use chrono::{DateTime, Utc};
+
+use std::cmp;
+use std::ops::Bound;
+
+use chart::chart::{Chart, seconds_between};
+use chart::interpolation::Interpolation;
+use chart::point::Point;
+
+impl Chart {
+  // The area under the chart from `start` to `end`, in value·seconds. The chart is taken to have
+  // the shape given by its interpolation and gap policy, so the area of a step or a curve is found
+  // exactly, and parts of the range where the chart has no value don't add anything.
+  pub fn integral(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Option<f64> {
+    debug!("CALLING chart.integral({:?}, {:?})", start, end);
+    self.integrate(start, end).map(|(area, _)| area)
+  }
+
+  // The average value of the chart from `start` to `end`, weighting each value by how long the
+  // chart has it for rather than by how many points there are. Only the parts of the range where
+  // the chart has a value are counted.
+  pub fn time_weighted_mean(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Option<f64> {
+    debug!("CALLING chart.time_weighted_mean({:?}, {:?})", start, end);
+    match self.integrate(start, end) {
+      Some((area, seconds)) if seconds > 0.0 => Some(area / seconds),
+      _ => None,
+    }
+  }
+
+  // Find the area under the chart from `start` to `end`, along with the number of seconds in
+  // between them that the chart has a value for. There's no area when the chart has no value
+  // anywhere in the range.
+  fn integrate(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Option<(f64, f64)> {
+    if end < start {
+      return None;
+    }
+    if end == start {
+      return Some((0.0, 0.0));
+    }
+
+    // The points in the range, along with enough points on either side of it to find the shape of
+    // the chart at its edges.
+    let count = self.points_needed_around(self.interpolation);
+    let mut points: Vec<Point> = self.range(Bound::Unbounded, Bound::Included(start))
+      .rev()
+      .take(count)
+      .cloned()
+      .collect();
+    points.reverse();
+    points.extend(self.range(Bound::Excluded(start), Bound::Excluded(end)).cloned());
+    points.extend(self.range(Bound::Included(end), Bound::Unbounded).take(count).cloned());
+
+    // Split the range at every point inside of it, so that each segment lies between two
+    // neighboring points (or off one end of the chart).
+    let mut edges = vec![start];
+    for point in self.range(Bound::Excluded(start), Bound::Excluded(end)) {
+      if edges[edges.len()-1] != point.timestamp {
+        edges.push(point.timestamp);
+      }
+    }
+    edges.push(end);
+
+    let mut total = None;
+    let mut split = 0;
+    for edge in edges.windows(2) {
+      let (segment_start, segment_end) = (edge[0], edge[1]);
+      while split < points.len() && points[split].timestamp <= segment_start {
+        split += 1;
+      }
+      let before: Vec<Point> = points[..split].iter().rev().take(count).cloned().collect();
+      let after: Vec<Point> = points[split..].iter().take(count).cloned().collect();
+
+      if let Some(area) = self.integrate_segment(segment_start, segment_end, &before, &after) {
+        let (total_area, total_seconds) = total.unwrap_or((0.0, 0.0));
+        let seconds = seconds_between(segment_start, segment_end);
+        total = Some((total_area + area, total_seconds + seconds));
+      }
+    }
+
+    total
+  }
+
+  // Find the area under the chart from `start` to `end`, which has no points in between them.
+  // `before` and `after` are the points on either side, each ordered from closest to furthest away.
+  fn integrate_segment(
+    &self,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    before: &[Point],
+    after: &[Point],
+  ) -> Option<f64> {
+    let seconds = seconds_between(start, end);
+    let (previous, next) = match (before.first(), after.first()) {
+      (Some(previous), Some(next)) => (previous, next),
+      (None, None) => return None,
+
+      // Off the end of the chart, the value is either held or continues in a straight line, so
+      // the average of the values at either end of the segment is exact.
+      (None, Some(_)) => {
+        let extrapolation = self.gap_policy.before_start;
+        let start_value = self.extrapolate(start, after, extrapolation);
+        let end_value = self.extrapolate(end, after, extrapolation);
+        return start_value.and_then(|start_value| end_value.map(|end_value| {
+          (start_value + end_value) / 2.0 * seconds
+        }));
+      },
+      (Some(_), None) => {
+        let extrapolation = self.gap_policy.after_end;
+        let start_value = self.extrapolate(start, before, extrapolation);
+        let end_value = self.extrapolate(end, before, extrapolation);
+        return start_value.and_then(|start_value| end_value.map(|end_value| {
+          (start_value + end_value) / 2.0 * seconds
+        }));
+      },
+    };
+    if self.gap_policy.is_gap(previous, next) {
+      return self.gap_policy.gap_value.map(|gap_value| gap_value * seconds);
+    }
+
+    let area = match self.interpolation {
+      Interpolation::Previous => previous.value * seconds,
+      Interpolation::Next => next.value * seconds,
+      Interpolation::Nearest => {
+        let gap = next.timestamp.signed_duration_since(previous.timestamp);
+        let halfway = cmp::max(start, cmp::min(end, previous.timestamp + gap / 2));
+        previous.value * seconds_between(start, halfway) +
+          next.value * seconds_between(halfway, end)
+      },
+      Interpolation::Linear => {
+        let start_value = self.interpolate_between_points(start, previous, next);
+        let end_value = self.interpolate_between_points(end, previous, next);
+        (start_value + end_value) / 2.0 * seconds
+      },
+
+      // Simpson's rule is exact for cubics.
+      Interpolation::MonotoneCubic => {
+        let middle = start + end.signed_duration_since(start) / 2;
+        let value_at = |timestamp| {
+          self.interpolate_around(timestamp, before, after, Interpolation::MonotoneCubic).unwrap()
+        };
+        (value_at(start) + 4.0 * value_at(middle) + value_at(end)) / 6.0 * seconds
+      },
+    };
+    Some(area)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+  use chart::gap_policy::{Extrapolation, GapPolicy};
+  use chart::interpolation::Interpolation;
+  use chart::test_helpers::{at, chart_with_values};
+
+  #[test]
+  fn it_integrates_under_the_chart() {
+    let chart = chart_with_values(&[(0.0, 0), (60.0, 60), (0.0, 120)], 2);
+    assert_eq!(chart.integral(at(0), at(120)), Some(3600.0));
+    assert_eq!(chart.integral(at(0), at(0)), Some(0.0));
+    assert_eq!(chart.integral(at(120), at(0)), None);
+
+    // Partial segments at the edges are clipped
+    assert_eq!(chart.integral(at(30), at(90)), Some(2700.0));
+    assert_eq!(chart.time_weighted_mean(at(30), at(90)), Some(45.0));
+    assert_eq!(chart.integral(at(10), at(20)), Some(150.0));
+  }
+
+  #[test]
+  fn it_weights_values_by_time_rather_than_by_point() {
+    // Lots of points while the value is high, and few while it's low
+    let mut values: Vec<(f64, i64)> = (0..10).map(|seconds| (100.0, seconds)).collect();
+    values.extend(&[(0.0, 10), (0.0, 60)]);
+
+    let mut chart = chart_with_values(&values, 2);
+    chart.interpolation = Interpolation::Previous;
+    assert_eq!(chart.integral(at(0), at(60)), Some(1000.0));
+    assert_eq!(chart.time_weighted_mean(at(0), at(60)), Some(1000.0 / 60.0));
+
+    for &(interpolation, integral) in &[
+      (Interpolation::Previous, 1000.0),
+      (Interpolation::Next, 900.0),
+      (Interpolation::Nearest, 950.0),
+      (Interpolation::Linear, 950.0),
+    ] {
+      chart.interpolation = interpolation;
+      assert_eq!(chart.integral(at(0), at(60)), Some(integral), "{:?}", interpolation);
+    }
+  }
+
+  #[test]
+  fn it_integrates_monotone_cubic_curves() {
+    let values = [(0.0, 0), (1.0, 10), (1.0, 20), (10.0, 25), (11.0, 60), (3.0, 70), (2.0, 100)];
+    let mut chart = chart_with_values(&values, 2);
+    chart.interpolation = Interpolation::MonotoneCubic;
+
+    // Compare against adding up thin slices of the curve
+    let (start, end) = (at(5), at(95));
+    let slices = 90_000;
+    let mut area = 0.0;
+    for slice in 0..slices {
+      let timestamp = start + Duration::milliseconds(slice) + Duration::microseconds(500);
+      area += chart.get_value(timestamp).unwrap() / 1000.0;
+    }
+    let integral = chart.integral(start, end).unwrap();
+    assert!((integral - area).abs() < 1e-3, "{} != {}", integral, area);
+  }
+
+  #[test]
+  fn it_only_counts_time_where_the_chart_has_a_value() {
+    let values = [(2.0, 0), (2.0, 60), (4.0, 600), (4.0, 660)];
+    let mut chart = chart_with_values(&values, 2);
+    assert_eq!(chart.integral(at(-60), at(720)), Some(2.0 * 60.0 + 3.0 * 540.0 + 4.0 * 60.0));
+    assert_eq!(chart.time_weighted_mean(at(-60), at(60)), Some(2.0));
+    assert_eq!(chart.time_weighted_mean(at(-60), at(-30)), None);
+
+    chart.gap_policy = GapPolicy {
+      max_gap: Some(Duration::minutes(5)),
+      before_start: Extrapolation::Hold,
+      after_end: Extrapolation::Hold,
+      ..GapPolicy::default()
+    };
+    assert_eq!(chart.integral(at(-60), at(720)), Some(2.0 * 120.0 + 4.0 * 120.0));
+    assert_eq!(chart.time_weighted_mean(at(-60), at(720)), Some(3.0));
+    assert_eq!(chart.time_weighted_mean(at(120), at(180)), None);
+
+    chart.gap_policy.gap_value = Some(0.0);
+    assert_eq!(chart.time_weighted_mean(at(-60), at(720)), Some(720.0 / 780.0));
+  }
+}
@@ -0,0 +1,234 @@
+extern crate chrono;
+use chrono::{DateTime, Utc};
+
+use std::cmp;
+
+use chart::chart::{Chart, seconds_between};
+use chart::point::Point;
+
+// How a chart finds values in between its points.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[derive(Default)]
+pub enum Interpolation {
+  // Hold the value of the point before, so the chart steps from one value to the next at each
+  // point. Good for setpoints and on/off states.
+  Previous,
+
+  // Use the value of the point after.
+  Next,
+
+  // Use the value of whichever point is closer. Exactly halfway between, the point after is used.
+  Nearest,
+
+  // Draw a straight line between the points on either side.
+  #[default]
+  Linear,
+
+  // Draw a smooth curve through the points that never overshoots them: between two points the
+  // curve only rises if the second is higher and only falls if it's lower (Fritsch-Butland). Good
+  // for smooth signals like temperatures.
+  MonotoneCubic,
+}
+
+impl Interpolation {
+  // How many points on each side of a timestamp are needed to interpolate a value there.
+  pub fn points_needed(&self) -> usize {
+    match *self {
+      Interpolation::MonotoneCubic => 2,
+      _ => 1,
+    }
+  }
+}
+
+// The slope that a monotone cubic curve should have at a point, given the length and slope of the
+// line coming into the point and the line going out of it. At peaks, troughs and flat spots the
+// slope is zero so that the curve doesn't overshoot the point, and otherwise it's a weighted
+// harmonic mean of the two (Fritsch-Butland).
+fn monotone_tangent(length_in: f64, slope_in: f64, length_out: f64, slope_out: f64) -> f64 {
+  if slope_in * slope_out <= 0.0 {
+    return 0.0;
+  }
+
+  let weight_in = 2.0 * length_out + length_in;
+  let weight_out = length_out + 2.0 * length_in;
+  (weight_in + weight_out) / (weight_in / slope_in + weight_out / slope_out)
+}
+
+// The length in seconds and slope of the line from `start` to `end`, unless the two are at the
+// same time.
+fn line_between(start: &Point, end: &Point) -> Option<(f64, f64)> {
+  let length = seconds_between(start.timestamp, end.timestamp);
+  if length > 0.0 {
+    Some((length, (end.value - start.value) / length))
+  } else {
+    None
+  }
+}
+
+// Evaluate a monotone cubic curve between `previous` and `next` at `timestamp`. The points beyond
+// them (if there are any) shape the curve; without them, the curve leaves that end in a straight
+// line towards the other point.
+fn monotone_cubic_interpolation(
+  timestamp: DateTime<Utc>,
+  before_previous: Option<&Point>,
+  previous: &Point,
+  next: &Point,
+  after_next: Option<&Point>,
+) -> f64 {
+  let (length, slope) = line_between(previous, next).unwrap();
+  let previous_tangent = match before_previous.and_then(|point| line_between(point, previous)) {
+    Some((length_in, slope_in)) => monotone_tangent(length_in, slope_in, length, slope),
+    None => slope,
+  };
+  let next_tangent = match after_next.and_then(|point| line_between(next, point)) {
+    Some((length_out, slope_out)) => monotone_tangent(length, slope, length_out, slope_out),
+    None => slope,
+  };
+
+  // Cubic Hermite spline, with `t` going from 0 at `previous` to 1 at `next`.
+  let t = seconds_between(previous.timestamp, timestamp) / length;
+  let t2 = t * t;
+  let t3 = t2 * t;
+  (2.0 * t3 - 3.0 * t2 + 1.0) * previous.value +
+    (t3 - 2.0 * t2 + t) * length * previous_tangent +
+    (-2.0 * t3 + 3.0 * t2) * next.value +
+    (t3 - t2) * length * next_tangent
+}
+
+impl Chart {
+  // How many points on each side of a timestamp are needed to find the value there, both for
+  // interpolating with `interpolation` and for extrapolating past the ends of the chart.
+  pub fn points_needed_around(&self, interpolation: Interpolation) -> usize {
+    cmp::max(interpolation.points_needed(), self.gap_policy.points_needed())
+  }
+
+  // Given the points around `timestamp` (before it, and at or after it, each ordered from closest
+  // to furthest away), find the value at `timestamp`. A point exactly on `timestamp` is used as-is.
+  // Otherwise, the chart's gap policy decides what happens when there isn't a point on both sides
+  // or the points on either side are too far apart, and `interpolation` is used in between.
+  pub fn interpolate_around(
+    &self,
+    timestamp: DateTime<Utc>,
+    before: &[Point],
+    after: &[Point],
+    interpolation: Interpolation,
+  ) -> Option<f64> {
+    let (previous, next) = match (before.first(), after.first()) {
+      (_, Some(next)) if next.timestamp == timestamp => return Some(next.value),
+      (Some(previous), Some(next)) => (previous, next),
+      (None, Some(_)) => return self.extrapolate(timestamp, after, self.gap_policy.before_start),
+      (Some(_), None) => return self.extrapolate(timestamp, before, self.gap_policy.after_end),
+      (None, None) => return None,
+    };
+    if self.gap_policy.is_gap(previous, next) {
+      return self.gap_policy.gap_value;
+    }
+
+    let value = match interpolation {
+      Interpolation::Previous => previous.value,
+      Interpolation::Next => next.value,
+      Interpolation::Nearest => {
+        let time_since_previous = seconds_between(previous.timestamp, timestamp);
+        let time_until_next = seconds_between(timestamp, next.timestamp);
+        if time_since_previous < time_until_next { previous.value } else { next.value }
+      },
+      Interpolation::Linear => self.interpolate_between_points(timestamp, previous, next),
+      Interpolation::MonotoneCubic => monotone_cubic_interpolation(
+        timestamp,
+        before.get(1),
+        previous,
+        next,
+        after.get(1),
+      ),
+    };
+    Some(value)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+  use chart::interpolation::Interpolation;
+  use chart::test_helpers::{at, chart_with_values};
+
+  #[test]
+  fn it_interpolates_with_each_interpolation() {
+    let chart = chart_with_values(&[(1.0, 0), (5.0, 60), (2.0, 120), (2.0, 180), (8.0, 240)], 2);
+
+    for &(seconds, interpolation, value) in &[
+      (75, Interpolation::Previous, 5.0),
+      (75, Interpolation::Next, 2.0),
+      (75, Interpolation::Nearest, 5.0),
+      (90, Interpolation::Nearest, 2.0),
+      (105, Interpolation::Nearest, 2.0),
+      (75, Interpolation::Linear, 4.25),
+
+      // Exactly on a point, the point's value is used no matter what
+      (120, Interpolation::Previous, 2.0),
+      (120, Interpolation::Next, 2.0),
+      (120, Interpolation::MonotoneCubic, 2.0),
+    ] {
+      let timestamp = at(seconds);
+      assert_eq!(chart.get_value_with(timestamp, interpolation), Some(value));
+      assert_eq!(chart.get_value_vec_with(timestamp, interpolation), Some(value));
+    }
+  }
+
+  #[test]
+  fn it_uses_the_interpolation_of_the_chart_by_default() {
+    let mut chart = chart_with_values(&[(1.0, 0), (5.0, 60)], 2);
+    chart.interpolation = Interpolation::Previous;
+    let timestamp = at(45);
+    assert_eq!(chart.get_value(timestamp), Some(1.0));
+    assert_eq!(chart.get_value_vec(timestamp), Some(1.0));
+    assert_eq!(chart.get_value_with(timestamp, Interpolation::Linear), Some(4.0));
+  }
+
+  #[test]
+  fn it_interpolates_monotone_cubic_curves_without_overshooting() {
+    let values = [(0.0, 0), (1.0, 10), (1.0, 20), (10.0, 25), (11.0, 60), (3.0, 70), (2.0, 100)];
+    let mut chart = chart_with_values(&values, 2);
+    chart.interpolation = Interpolation::MonotoneCubic;
+
+    for pair in values.windows(2) {
+      let ((start_value, start_seconds), (end_value, end_seconds)) = (pair[0], pair[1]);
+      let mut last_value = start_value;
+      for tenths in start_seconds * 10 + 1..end_seconds * 10 {
+        let timestamp = at(0) + Duration::milliseconds(tenths * 100);
+        let value = chart.get_value(timestamp).unwrap();
+        assert_eq!(Some(value), chart.get_value_vec(timestamp));
+
+        // Never outside of the two points on either side, and always heading from one to the other
+        assert!(value >= start_value.min(end_value) && value <= start_value.max(end_value));
+        if end_value >= start_value {
+          assert!(value >= last_value);
+        } else {
+          assert!(value <= last_value);
+        }
+        last_value = value;
+      }
+    }
+
+    // In between points with the same value, the curve stays flat
+    assert_eq!(chart.get_value(at(15)), Some(1.0));
+
+    // It's a curve rather than a line
+    let timestamp = at(40);
+    assert!(chart.get_value(timestamp) != chart.get_value_with(timestamp, Interpolation::Linear));
+  }
+
+  #[test]
+  fn it_interpolates_monotone_cubic_curves_along_straight_lines() {
+    let mut chart = chart_with_values(&[(0.0, 0), (2.0, 20), (3.0, 30), (7.0, 70)], 2);
+    chart.interpolation = Interpolation::MonotoneCubic;
+    for seconds in 0..70 {
+      let timestamp = at(seconds);
+      let value = chart.get_value(timestamp).unwrap();
+      assert!((value - seconds as f64 / 10.0).abs() < 1e-9);
+    }
+  }
+}
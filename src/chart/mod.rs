@@ -1,5 +1,18 @@
+pub mod aggregation;
 pub mod point;
 pub mod chart;
 pub mod chart_index;
+pub mod derivative;
+pub mod downsample;
+pub mod gap_policy;
+pub mod integral;
+pub mod interpolation;
 pub mod point_index;
 pub mod projection;
+pub mod quantile;
+pub mod range;
+pub mod resample;
+pub mod rolling;
+
+#[cfg(test)]
+mod test_helpers;
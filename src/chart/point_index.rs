@@ -1,31 +1,110 @@
 extern crate chrono;
-use chrono::{DateTime, Utc, TimeZone};
+use chrono::{DateTime, Utc};
 
+use std::sync::OnceLock;
+
+use chart::aggregation::Summary;
 use chart::point::Point;
+use chart::quantile::QuantileSketch;
 
+// A node in a chart's index. The index is a B+tree stored in `Chart.index`, with the root always at
+// position zero. Nodes refer to each other by their position in that vector.
+//
+// Inner nodes have `children` and `keys`, but no `data`. `keys[i]` separates `children[i]` from
+// `children[i+1]`: every point under `children[i]` is at or before `keys[i]`, and every point
+// under `children[i+1]` is at or after it.
+//
+// Leaves have `data`, but no `children` or `keys`. Each leaf links to the leaf before it (`less`)
+// and the leaf after it (`more`), so that neighboring points can be found without walking the tree.
+//
+// Every node also keeps a `summary` of all of the points under it, so that aggregations over a
+// range can use whole subtrees at once instead of visiting every point. Inner nodes can also keep a
+// `sketch` of the values under them for finding quantiles. That's only built the first time a
+// quantile needs it, and is thrown away whenever the node's summary changes. It's only a cache, so
+// it's left out when comparing nodes.
 #[derive(Debug)]
 #[derive(Clone)]
-#[derive(PartialEq)]
 pub struct PointIndex {
-  pub timestamp: Option<DateTime<Utc>>,
-  pub less: usize,
-  pub more: usize,
+  pub keys: Vec<DateTime<Utc>>,
+  pub children: Vec<usize>,
+  pub less: Option<usize>,
+  pub more: Option<usize>,
   pub parent: usize,
   pub data: Option<Vec<Point>>,
+  pub summary: Summary,
+  pub sketch: OnceLock<QuantileSketch>,
+}
+
+impl PartialEq for PointIndex {
+  fn eq(&self, other: &PointIndex) -> bool {
+    self.keys == other.keys &&
+      self.children == other.children &&
+      self.less == other.less &&
+      self.more == other.more &&
+      self.parent == other.parent &&
+      self.data == other.data &&
+      self.summary == other.summary
+  }
 }
 
 impl PointIndex {
+  pub fn new_leaf(data: Vec<Point>, parent: usize) -> PointIndex {
+    PointIndex {
+      keys: vec![],
+      children: vec![],
+      less: None,
+      more: None,
+      parent: parent,
+      summary: Summary::from_points(&data),
+      sketch: OnceLock::new(),
+      data: Some(data),
+    }
+  }
+
+  pub fn new_node(keys: Vec<DateTime<Utc>>, children: Vec<usize>, parent: usize) -> PointIndex {
+    PointIndex {
+      keys: keys,
+      children: children,
+      less: None,
+      more: None,
+      parent: parent,
+      data: None,
+      summary: Summary::new(),
+      sketch: OnceLock::new(),
+    }
+  }
+
+  // A copy of this leaf holding `data` instead, in the same place in the tree. The summary is
+  // worked out again for the new points.
+  pub fn with_data(&self, data: Vec<Point>) -> PointIndex {
+    PointIndex {
+      keys: self.keys.clone(),
+      children: self.children.clone(),
+      less: self.less,
+      more: self.more,
+      parent: self.parent,
+      summary: Summary::from_points(&data),
+      sketch: OnceLock::new(),
+      data: Some(data),
+    }
+  }
+
+  pub fn is_leaf(&self) -> bool {
+    self.data.is_some()
+  }
+
+  // The number of points in a leaf, or the number of children of an inner node.
+  pub fn len(&self) -> usize {
+    match self.data {
+      Some(ref data) => data.len(),
+      None => self.children.len(),
+    }
+  }
 
   // Return the default value used in projections - ie, when an node in the index tree is removed
   // due to a filter, what should the value be? Eventually, it may be a good idea to make this a
   // shared read-only static reference instead of giving each projection its own default value.
   pub fn new_default_value() -> PointIndex {
-    PointIndex{
-      timestamp: Some(Utc.ymd(1970, 1, 1).and_hms(0, 0, 0)),
-      less: 0,
-      more: 0,
-      parent: 0,
-      data: Some(vec![]),
-    }
+    PointIndex::new_leaf(vec![], 0)
   }
 }
@@ -1,107 +1,174 @@
 extern crate chrono;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 
-use chart::chart::Chart;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+
+use chart::chart::{Chart, duration_in_nanoseconds};
 use chart::point::Point;
 use chart::point_index::PointIndex;
 
-/* pub trait ChartProjectionOperation<'a> { */
-/*   // Apply is called for each point index node to determine how it should be modified in the */
-/*   // projection. */
-/*   //  */
-/*   // Three cases: */
-/*   // Return a Some(index_node), which means keep the point index */
-/*   // Return a Some(something_else), which means replace the point index with something_else */
-/*   // Return None, which means don't include the point index */
-/*   fn apply(&self, index_node: &'a PointIndex) -> Option<&PointIndex>; */
-/* } */
+// What a projection operation does with a leaf of the index.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum ProjectionOperationResult {
+  // Leave the leaf as it is.
+  Keep,
+
+  // Use this leaf in its place, usually one made with `PointIndex::with_data`.
+  Replace(Box<PointIndex>),
+
+  // Leave the leaf out of the projection altogether.
+  Delete,
+}
+
+pub trait ChartProjectionOperation {
+  // Apply is called for each leaf in the projection to determine how it should be modified. Each
+  // operation sees the leaf as the operations before it left it.
+  fn apply(&self, index_node: &PointIndex) -> ProjectionOperationResult;
+
+  // Operations that move points in time have to say where a timestamp in their output came from,
+  // so that lookups can find the right leaf in the chart. Timestamps have to stay in the same
+  // order once they're moved.
+  fn source_time(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    timestamp
+  }
+}
+
+// Any closure from a leaf to a result is an operation, so that operations can capture whatever
+// state they need.
+impl<F> ChartProjectionOperation for F where F: Fn(&PointIndex) -> ProjectionOperationResult {
+  fn apply(&self, index_node: &PointIndex) -> ProjectionOperationResult {
+    self(index_node)
+  }
+}
+
+
+// The most leaves that a projection keeps the projected copies of, unless it's told otherwise.
+pub const PROJECTION_CACHE_CAPACITY: usize = 1024;
+
+// What a leaf of the chart looks like through a projection, once that's been worked out.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+enum ProjectedLeaf {
+  // The leaf as it is in the chart.
+  Original,
 
+  // The projection's empty default value.
+  Default,
 
+  // A copy of the leaf changed by the projection, at this position in the arena.
+  Projected(usize),
+}
+
+// The scratch arena of a projection, which caches how each leaf of the chart looks through the
+// projection so that the operations only have to be applied to it once. Leaves are forgotten
+// oldest first once there are more than the projection's `cache_capacity` of them, and all of
+// them are forgotten when the chart changes.
 pub struct ProjectionDisposable {
     nodes: Vec<PointIndex>,
+
+    // Positions in `nodes` of leaves that have been forgotten, ready to be used again.
+    free: Vec<usize>,
+
+    leaves: HashMap<usize, ProjectedLeaf>,
+
+    // The leaves in `leaves`, oldest first.
+    order: VecDeque<usize>,
+
+    // The version of the chart the leaves came from.
+    version: Option<u64>,
 }
 
 impl ProjectionDisposable {
     fn new() -> ProjectionDisposable {
         ProjectionDisposable {
             nodes: vec![],
+            free: vec![],
+            leaves: HashMap::new(),
+            order: VecDeque::new(),
+            version: None,
         }
     }
-    
+
     fn add(&mut self, point: PointIndex) -> usize {
-        self.nodes.push(point);
-        self.nodes.len()-1
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = point;
+                index
+            },
+            None => {
+                self.nodes.push(point);
+                self.nodes.len()-1
+            },
+        }
     }
-    
+
     fn get(&self, index: usize) -> &PointIndex {
         &self.nodes[index]
     }
-}
-
-
-pub struct ProjectionOperationResult {
-  pub action: &'static str,
-  pub index: Option<usize>,
-}
-
-impl ProjectionOperationResult {
-  fn keep() -> ProjectionOperationResult {
-    ProjectionOperationResult { action: "KEEP", index: None }
-  }
-  fn replace(index: usize) -> ProjectionOperationResult {
-    ProjectionOperationResult { action: "REPLACE", index: Some(index) }
-  }
-  fn delete() -> ProjectionOperationResult {
-    ProjectionOperationResult { action: "DELETE", index: None }
-  }
-}
 
+    // Throw away every node, once nothing refers to them any more.
+    fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.leaves.clear();
+        self.order.clear();
+    }
 
-pub struct ProjectionOperationNode<'a> {
-  node: Option<&'a PointIndex>,
-  index: usize,
-}
-impl<'a> ProjectionOperationNode<'a> {
-  fn new(point_index: &'a PointIndex) -> ProjectionOperationNode {
-    ProjectionOperationNode {
-      node: Some(point_index),
-      index: 0,
+    // Forget everything if the leaves were projected from another chart, or an older version of
+    // this one.
+    fn check_chart(&mut self, chart: &Chart) {
+        if self.version != Some(chart.version) {
+            self.clear();
+            self.version = Some(chart.version);
+        }
     }
-  }
 
-  fn update_index(&mut self, index: usize) {
-    self.node = None;
-    self.index = index;
-  }
+    fn cached(&self, node_index: usize) -> Option<ProjectedLeaf> {
+        self.leaves.get(&node_index).cloned()
+    }
 
-  fn value(&'a self, disposable: &'a ProjectionDisposable) -> &PointIndex {
-    match self.node {
-      Some(ref node) => node,
-      None => disposable.get(self.index),
+    // Remember how the leaf at `node_index` was projected, forgetting the oldest leaves if there
+    // are now more than `capacity` of them. The leaf just added is always kept.
+    fn remember(&mut self, node_index: usize, leaf: ProjectedLeaf, capacity: usize) {
+        self.leaves.insert(node_index, leaf);
+        self.order.push_back(node_index);
+        while self.order.len() > cmp::max(capacity, 1) {
+            let oldest = self.order.pop_front().unwrap();
+            if let Some(ProjectedLeaf::Projected(index)) = self.leaves.remove(&oldest) {
+                self.nodes[index] = PointIndex::new_default_value();
+                self.free.push(index);
+            }
+        }
     }
-  }
 }
 
 
-
-
+// An operation that maps each point in the projection through `predicate`.
 pub struct ProjectionOperation {
-  predicate: fn(&Point) -> Point,
+  predicate: Box<dyn Fn(&Point) -> Point>,
 }
 impl ProjectionOperation {
-  fn apply(
-    &self,
-    node: &PointIndex,
-    projection: &Projection,
-    disposable: &mut ProjectionDisposable,
-  ) -> ProjectionOperationResult {
+  pub fn new<F>(predicate: F) -> ProjectionOperation where F: Fn(&Point) -> Point + 'static {
+    ProjectionOperation { predicate: Box::new(predicate) }
+  }
+}
+
+impl ChartProjectionOperation for ProjectionOperation {
+  fn apply(&self, node: &PointIndex) -> ProjectionOperationResult {
     let mut results: Vec<Point> = vec![];
     let mut output_identical_to_input = true;
 
     if let Some(ref data) = node.data {
       // Map each point in `data` into `results`.
       for input_point in data {
-        let output_point = (self.predicate)(&input_point);
+        let output_point = (self.predicate)(input_point);
         if input_point != &output_point {
           output_identical_to_input = false;
         }
@@ -111,32 +178,159 @@ impl ProjectionOperation {
       if output_identical_to_input {
         // Return the original index node, since the mapping operation did nothing to any items in
         // it.
-        ProjectionOperationResult::keep()
+        ProjectionOperationResult::Keep
       } else {
         // Replace the index node with the updates from the mapping operation.
-        let pt = node.clone();
-        ProjectionOperationResult::replace(disposable.add(pt))
+        ProjectionOperationResult::Replace(Box::new(node.with_data(results)))
       }
     } else {
       // No data in the node? It's not a leaf, and map can't do anything with it.
-      ProjectionOperationResult::keep()
+      ProjectionOperationResult::Keep
     }
   }
 }
 
 
+// An operation that leaves out the points in the projection that `predicate` returns false for.
+// Lookups through the projection then interpolate across the points that are left, as though the
+// ones taken out were never there.
+pub struct ProjectionFilter {
+  predicate: Box<dyn Fn(&Point) -> bool>,
+}
+impl ProjectionFilter {
+  pub fn new<F>(predicate: F) -> ProjectionFilter where F: Fn(&Point) -> bool + 'static {
+    ProjectionFilter { predicate: Box::new(predicate) }
+  }
+
+  // Keep the points with a value of at least `threshold`.
+  pub fn at_least(threshold: f64) -> ProjectionFilter {
+    ProjectionFilter::new(move |point| point.value >= threshold)
+  }
+
+  // Keep the points with a value of at most `threshold`.
+  pub fn at_most(threshold: f64) -> ProjectionFilter {
+    ProjectionFilter::new(move |point| point.value <= threshold)
+  }
+
+  // Keep the points with a value from `min` to `max` (both inclusive), leaving out any outliers
+  // beyond them.
+  pub fn within(min: f64, max: f64) -> ProjectionFilter {
+    if min > max {
+      panic!("Filter bounds have to be in order, got {} and {}!", min, max);
+    }
+    ProjectionFilter::new(move |point| point.value >= min && point.value <= max)
+  }
+
+  // Leave out the points with a NaN value.
+  pub fn without_nan() -> ProjectionFilter {
+    ProjectionFilter::new(|point| !point.value.is_nan())
+  }
+}
+
+impl ChartProjectionOperation for ProjectionFilter {
+  fn apply(&self, node: &PointIndex) -> ProjectionOperationResult {
+    let data = match node.data {
+      Some(ref data) => data,
+      None => return ProjectionOperationResult::Keep,
+    };
+
+    let results: Vec<Point> = data.iter()
+      .filter(|point| (self.predicate)(point))
+      .cloned()
+      .collect();
+    if results.len() == data.len() {
+      ProjectionOperationResult::Keep
+    } else if results.is_empty() {
+      ProjectionOperationResult::Delete
+    } else {
+      ProjectionOperationResult::Replace(Box::new(node.with_data(results)))
+    }
+  }
+}
+
+
+// An operation that moves every point in the projection in time: first stretching the time since
+// `origin` by `scale`, and then shifting it along by `shift`. Looking up a timestamp in a chart
+// shifted by a week gives the value from the week before.
+pub struct ProjectionTimeShift {
+  origin: DateTime<Utc>,
+  scale: f64,
+  shift: Duration,
+}
+impl ProjectionTimeShift {
+  pub fn new(origin: DateTime<Utc>, scale: f64, shift: Duration) -> ProjectionTimeShift {
+    if !(scale > 0.0 && scale.is_finite()) {
+      panic!("Time can only be scaled by a positive amount, got {}!", scale);
+    }
+    ProjectionTimeShift { origin: origin, scale: scale, shift: shift }
+  }
+
+  // Move every point `shift` later (or earlier, for a negative `shift`).
+  pub fn shift(shift: Duration) -> ProjectionTimeShift {
+    ProjectionTimeShift::new(Utc.timestamp(0, 0), 1.0, shift)
+  }
+
+  // Stretch the time between each point and `origin` by `scale`, so that a scale of two makes the
+  // chart twice as long.
+  pub fn scale(origin: DateTime<Utc>, scale: f64) -> ProjectionTimeShift {
+    ProjectionTimeShift::new(origin, scale, Duration::zero())
+  }
+
+  fn scale_since_origin(&self, timestamp: DateTime<Utc>, scale: f64) -> DateTime<Utc> {
+    if scale == 1.0 {
+      return timestamp;
+    }
+    let nanoseconds = duration_in_nanoseconds(self.origin, timestamp) * scale;
+    self.origin + Duration::nanoseconds(nanoseconds.round() as i64)
+  }
+}
+
+impl ChartProjectionOperation for ProjectionTimeShift {
+  fn apply(&self, node: &PointIndex) -> ProjectionOperationResult {
+    match node.data {
+      Some(ref data) => {
+        let moved = data.iter()
+          .map(|point| {
+            let timestamp = self.scale_since_origin(point.timestamp, self.scale) + self.shift;
+            Point::new(point.value, timestamp)
+          })
+          .collect();
+        ProjectionOperationResult::Replace(Box::new(node.with_data(moved)))
+      },
+      None => ProjectionOperationResult::Keep,
+    }
+  }
+
+  fn source_time(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    self.scale_since_origin(timestamp - self.shift, 1.0 / self.scale)
+  }
+}
+
+
+// A view of a chart from `start_time` to `end_time`, with `operations` applied to its points. The
+// range is of the chart's own timestamps, before any operations move them. The chart itself is
+// never changed: the leaves that the operations change are copied into a scratch
+// arena that the projection owns, which is why lookups through a projection only need to borrow
+// it. Create one with `Chart::new_projection`.
 pub struct Projection {
-  pub operations: Vec<Box<ProjectionOperation> /* box ensures that each has the same size */>,
+  // Boxed, so that operations of different types can share the vector. The operations and the range
+  // are private, as the cached leaves depend on them.
+  operations: Vec<Box<dyn ChartProjectionOperation>>,
 
-  pub start_time: DateTime<Utc>,
-  pub end_time: DateTime<Utc>,
+  start_time: DateTime<Utc>,
+  end_time: DateTime<Utc>,
   pub default_value: PointIndex,
+
+  // The most leaves to keep the projected copies of between lookups.
+  pub cache_capacity: usize,
+
+  disposable: RefCell<ProjectionDisposable>,
 }
 
 impl Projection {
   fn new(
     start_time: DateTime<Utc>, end_time: DateTime<Utc>,
-    operations: Vec<Box<ProjectionOperation>>,
+    operations: Vec<Box<dyn ChartProjectionOperation>>,
   ) -> Projection {
     Projection {
       operations: operations,
@@ -144,68 +338,599 @@ impl Projection {
       start_time: start_time,
       end_time: end_time,
       default_value: PointIndex::new_default_value(),
+
+      cache_capacity: PROJECTION_CACHE_CAPACITY,
+
+      disposable: RefCell::new(ProjectionDisposable::new()),
     }
   }
+
+  pub fn operations(&self) -> &[Box<dyn ChartProjectionOperation>] {
+    &self.operations
+  }
+
+  pub fn start_time(&self) -> DateTime<Utc> {
+    self.start_time
+  }
+
+  pub fn end_time(&self) -> DateTime<Utc> {
+    self.end_time
+  }
+
+  // Move the projection to look at the chart from `start_time` to `end_time` instead, forgetting
+  // the leaves projected for the old range.
+  pub fn set_range(&mut self, start_time: DateTime<Utc>, end_time: DateTime<Utc>) {
+    self.start_time = start_time;
+    self.end_time = end_time;
+    self.disposable.get_mut().clear();
+  }
+
+  // Find the timestamp in the chart that ends up at `timestamp` in the projection.
+  pub fn source_time(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    self.operations.iter().rev().fold(timestamp, |timestamp, operation| {
+      operation.source_time(timestamp)
+    })
+  }
+
+  // Chain `next` on after this projection, making a projection that applies this projection's
+  // operations and then `next`'s to whatever they leave. The range of `next` is of the timestamps
+  // that come out of this projection, and only the points within both ranges are kept. Chain as
+  // many projections as needed to build up a pipeline, one stage at a time.
+  pub fn then(self, next: Projection) -> Projection {
+    let start_time = cmp::max(self.start_time, self.source_time(next.start_time));
+    let end_time = cmp::min(self.end_time, self.source_time(next.end_time));
+    let cache_capacity = self.cache_capacity;
+
+    let mut operations = self.operations;
+    operations.extend(next.operations);
+    let mut projection = Projection::new(start_time, end_time, operations);
+    projection.cache_capacity = cache_capacity;
+    projection
+  }
 }
 
 impl Chart {
-  pub fn project_index_node<'a>(
+  // Create a projection of this chart from `start_time` to `end_time` (both inclusive), which
+  // applies `operations` in order to the points in it. Pass it to `get_value_projection` to look
+  // up values in the projected chart.
+  pub fn new_projection(
+    &self,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    operations: Vec<Box<dyn ChartProjectionOperation>>,
+  ) -> Projection {
+    debug!(
+      "CALLING chart.new_projection({:?}, {:?}, <{} operations>)",
+      start_time, end_time, operations.len(),
+    );
+    Projection::new(start_time, end_time, operations)
+  }
+
+  // A new chart with the points of this chart as they look through `projection`, with its own
+  // index and the same settings as this chart. The new chart has nothing more to do with this one,
+  // so it can be changed or kept after this one is gone.
+  pub fn materialize(&self, projection: &Projection) -> Chart {
+    debug!("CALLING chart.materialize(<projection>)");
+    let mut points = vec![];
+    self.with_projection_disposable(Some(projection), |mut projection_disposable| {
+      // Only the leaves from the one holding the start of the projection's range up to the one
+      // holding its end have points in the projection.
+      let mut next_index = self.lookup_first_in_index(projection.start_time);
+      while let Some(node_index) = next_index {
+        if let Some(first) = self.index[node_index].data.as_ref().and_then(|data| data.first()) {
+          if first.timestamp > projection.end_time {
+            break;
+          }
+        }
+
+        let node = self.project_index_node(node_index, &mut projection_disposable);
+        if let Some(ref data) = node.data {
+          points.extend(data.iter().cloned());
+        }
+        next_index = self.index[node_index].more;
+      }
+    });
+    self.with_points(points)
+  }
+
+  // Run `lookup` with the projection (if any) and its scratch arena, ready to be passed on to
+  // `project_index_node`. Leaves cached by earlier lookups are kept, unless this chart has changed
+  // since.
+  pub fn with_projection_disposable<'a, T, F>(
+    &'a self,
+    projection: Option<&'a Projection>,
+    lookup: F,
+  ) -> T
+    where F: for<'b> FnOnce(Option<(&'b Projection, &'b mut ProjectionDisposable)>) -> T
+  {
+    match projection {
+      Some(projection) => {
+        let mut disposable = projection.disposable.borrow_mut();
+        disposable.check_chart(self);
+        lookup(Some((projection, &mut disposable)))
+      },
+      None => lookup(None),
+    }
+  }
+
+  // Return the node at `node_index` as it looks through the projection. Leaves with no points in
+  // the projection's range, or deleted by one of its operations, come back as the projection's
+  // empty default value. Leaves that were trimmed to the range or changed by an operation are
+  // stored in the disposable arena, and the copy in the arena is returned. How each leaf was
+  // projected is cached, so later lookups that reach the same leaf don't apply the operations
+  // again. Without a projection, the node is returned as is.
+  //
+  // The projection is borrowed rather than taken, so that it can go on to project the next node
+  // once the caller is done with this one.
+  pub fn project_index_node<'a, 'b, 'c>(
     &'a self,
     node_index: usize,
-    mut projection_disposable: Option<(&'a Projection, &'a mut ProjectionDisposable)>,
-  ) -> (&'a PointIndex, Option<(&'a Projection, &'a mut ProjectionDisposable)>) {
+    projection_disposable: &'c mut Option<(&'b Projection, &'b mut ProjectionDisposable)>,
+  ) -> &'c PointIndex where 'a: 'c {
     debug!(
       "CALLING chart.project_index_node({}, {})",
       node_index,
       if projection_disposable.is_some() { "<projection>" } else { "None" }
     );
 
-    let mut accumulator = &self.index[node_index];
-
-    match projection_disposable {
-      // No projection, perform a mapping and that's it
-      None => (),
-
-      // Project the index node.
-      Some((ref projection, ref mut disposable)) => {
-        if let Some(ref data) = self.index[node_index].data {
-
-          if data.len() == 0 {
-            debug!("No data in node to project, so just returning node.");
-            // No data, so it's not required to copy the node since no items exist to filter anyway
-            return (accumulator, None)
-          } else {
-            // If outside of the range the projection applies to, then disregard.
-            if data[0].timestamp < projection.start_time || data[data.len()-1].timestamp > projection.end_time {
-              /* debug!( */
-              /*   "Node index {} out of projection range (timestamp:{:?} start_time:{} end_time{}), returning default", */
-              /*   node_index, accumulator.timestamp, projection.start_time, projection.end_time, */
-              /* ); */
-              return (&projection.default_value, None);
-            }
+    let node = &self.index[node_index];
+    let (projection, disposable) = match *projection_disposable {
+      // No projection, so the node is used as it is.
+      None => return node,
+      Some((projection, ref mut disposable)) => (projection, &mut **disposable),
+    };
 
-            // Apply projection operations
-            for operation in &projection.operations {
-              match operation.apply(accumulator, projection, disposable) {
-                ProjectionOperationResult { action: "KEEP", index: _ } => (),
-                ProjectionOperationResult { action: "REPLACE", index: Some(index)} => {
-                  /* FIXME FIXME FIXME */
-                  accumulator = accumulator;
-                },
-                ProjectionOperationResult { action: "DELETE", index: _ } => {
-                  return (&projection.default_value, None);
-                },
-                _ => (),
-              }
-            }
-          }
-        } else {
-          return (&self.index[node_index], None)
-        }
+    // Work out how the leaf looks through the projection, unless that's already cached.
+    let leaf = match disposable.cached(node_index) {
+      Some(leaf) => leaf,
+      None => {
+        let leaf = match self.project_leaf(node_index, projection) {
+          None => ProjectedLeaf::Default,
+          Some(Cow::Borrowed(_)) => ProjectedLeaf::Original,
+          Some(Cow::Owned(projected)) => ProjectedLeaf::Projected(disposable.add(projected)),
+        };
+        disposable.remember(node_index, leaf, projection.cache_capacity);
+        leaf
+      },
+    };
+
+    match leaf {
+      ProjectedLeaf::Original => node,
+      ProjectedLeaf::Default => &projection.default_value,
+      ProjectedLeaf::Projected(index) => disposable.get(index),
+    }
+  }
+
+  // Apply `projection` to the node at `node_index`. This is the node itself when the projection
+  // leaves it as it is, a changed copy of it, or nothing when none of its points are left.
+  fn project_leaf<'a>(
+    &'a self,
+    node_index: usize,
+    projection: &Projection,
+  ) -> Option<Cow<'a, PointIndex>> {
+    let node = &self.index[node_index];
+    let data = match node.data {
+      Some(ref data) if data.is_empty() => {
+        debug!("No data in node to project, so just returning node.");
+        // No data, so it's not required to copy the node since no items exist to filter anyway
+        return Some(Cow::Borrowed(node));
+      },
+      Some(ref data) => data,
+      // Not a leaf, so there's nothing to project.
+      None => return Some(Cow::Borrowed(node)),
+    };
+
+    // Find the points within the range the projection applies to. If there aren't any, then
+    // disregard the node.
+    let from = data.partition_point(|point| point.timestamp < projection.start_time);
+    let to = data.partition_point(|point| point.timestamp <= projection.end_time);
+    if from >= to {
+      debug!("Node index {} out of projection range, returning default", node_index);
+      return None;
+    }
+
+    // Trim leaves that straddle either edge of the range down to the points inside it.
+    let mut accumulator = Cow::Borrowed(node);
+    if from > 0 || to < data.len() {
+      debug!("Node index {} straddles the projection range, trimming it", node_index);
+      accumulator = Cow::Owned(node.with_data(data[from..to].to_vec()));
+    }
+
+    // Apply projection operations, each to the result of the one before it.
+    for operation in &projection.operations {
+      match operation.apply(&accumulator) {
+        ProjectionOperationResult::Keep => (),
+        ProjectionOperationResult::Replace(replacement) => {
+          accumulator = Cow::Owned(*replacement);
+        },
+        ProjectionOperationResult::Delete => {
+          return None;
+        },
+      }
+    }
+
+    Some(accumulator)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+  use chart::chart::Chart;
+  use chart::point::Point;
+  use chart::point_index::PointIndex;
+  use chart::projection::{
+    ChartProjectionOperation, ProjectionFilter, ProjectionOperation, ProjectionOperationResult,
+    ProjectionTimeShift,
+  };
+  use chart::test_helpers::{at, chart_with_a_point_every};
+  use std::cell::Cell;
+  use std::rc::Rc;
+
+  // A chart with a point every ten seconds, with the given values.
+  fn ten_second_chart(values: &[f64]) -> Chart {
+    chart_with_a_point_every(Duration::seconds(10), values.iter().cloned(), 3)
+  }
+
+  #[test]
+  fn it_gets_mapped_values_within_projection() {
+    let chart = ten_second_chart(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+    let projection = chart.new_projection(at(0), at(80), vec![
+      Box::new(ProjectionOperation::new(|point| Point::new(point.value * 10.0, point.timestamp))),
+    ]);
+
+    assert_eq!(chart.get_value_projection(at(20), Some(&projection)), Some(30.0));
+    assert!(!projection.disposable.borrow().nodes.is_empty());
+
+    // Interpolating between points in different leaves uses the mapped points on both sides
+    assert_eq!(chart.get_value_projection(at(25), Some(&projection)), Some(35.0));
+    assert_eq!(chart.get_value_projection(at(75), Some(&projection)), Some(85.0));
+
+    // The same projection can be used for any number of lookups, without its arena growing
+    for seconds in 0..80 {
+      let expected = 10.0 + seconds as f64;
+      assert_eq!(chart.get_value_projection(at(seconds), Some(&projection)), Some(expected));
+    }
+    assert!(projection.disposable.borrow().nodes.len() <= 3);
+
+    // The chart itself is left as it was
+    assert_eq!(chart.get_value(at(20)), Some(3.0));
+  }
+
+  #[test]
+  fn it_applies_each_operation_to_the_result_of_the_one_before() {
+    let chart = ten_second_chart(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    let projection = chart.new_projection(at(0), at(50), vec![
+      Box::new(ProjectionOperation::new(|point| Point::new(point.value * 2.0, point.timestamp))),
+      Box::new(ProjectionOperation::new(|point| Point::new(point.value + 1.0, point.timestamp))),
+    ]);
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), Some(11.0));
+
+    // Operations that don't change anything keep the original leaves
+    let projection = chart.new_projection(at(0), at(50), vec![
+      Box::new(ProjectionOperation::new(|point| point.clone())),
+    ]);
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), Some(5.0));
+    assert_eq!(projection.disposable.borrow().nodes.len(), 0);
+  }
+
+  // Leaves out every leaf that has a point above `limit` in it.
+  struct DeleteLeavesAbove {
+    limit: f64,
+  }
+
+  impl ChartProjectionOperation for DeleteLeavesAbove {
+    fn apply(&self, index_node: &PointIndex) -> ProjectionOperationResult {
+      if index_node.summary.max > self.limit {
+        ProjectionOperationResult::Delete
+      } else {
+        ProjectionOperationResult::Keep
       }
     }
+  }
+
+  #[test]
+  fn it_applies_operations_that_implement_the_trait() {
+    let chart = ten_second_chart(&[1.0, 2.0, 3.0, 4.0, 50.0, 6.0, 7.0, 8.0, 9.0]);
+    let projection = chart.new_projection(at(0), at(80), vec![
+      Box::new(DeleteLeavesAbove { limit: 10.0 }),
+    ]);
+    assert_eq!(chart.get_value_projection(at(10), Some(&projection)), Some(2.0));
+
+    // The leaf with the spike in it is gone, so the value comes from the leaves on either side
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), Some(5.0));
+    assert_eq!(chart.get_value_projection(at(70), Some(&projection)), Some(8.0));
+  }
+
+  #[test]
+  fn it_applies_closures_that_capture_state() {
+    let chart = ten_second_chart(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+    // Count how many leaves the projection visits, and move every point up by an offset
+    let visited = Rc::new(Cell::new(0));
+    let offset = 100.0;
+    let counter = visited.clone();
+    let projection = chart.new_projection(at(0), at(50), vec![
+      Box::new(move |index_node: &PointIndex| {
+        counter.set(counter.get() + 1);
+        let data = index_node.data.as_ref().unwrap();
+        let moved = data.iter().map(|point| Point::new(point.value + offset, point.timestamp));
+        ProjectionOperationResult::Replace(Box::new(index_node.with_data(moved.collect())))
+      }),
+    ]);
+    assert_eq!(chart.get_value_projection(at(25), Some(&projection)), Some(103.5));
+    assert!(visited.get() > 0);
+
+    // Map operations can capture state too
+    let scale = 3.0;
+    let projection = chart.new_projection(at(0), at(50), vec![
+      Box::new(ProjectionOperation::new(move |point| {
+        Point::new(point.value * scale, point.timestamp)
+      })),
+    ]);
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), Some(15.0));
+  }
+
+  #[test]
+  fn it_interpolates_across_filtered_out_points() {
+    let chart = ten_second_chart(&[1.0, 2.0, f64::NAN, 4.0, 5.0, 6.0, 7.0]);
+    let projection = chart.new_projection(at(0), at(60), vec![
+      Box::new(ProjectionFilter::without_nan()),
+    ]);
+    assert_eq!(chart.get_value(at(20)).map(|value| value.is_nan()), Some(true));
+    assert_eq!(chart.get_value_projection(at(20), Some(&projection)), Some(3.0));
+    assert_eq!(chart.get_value_projection(at(15), Some(&projection)), Some(2.5));
+    assert_eq!(chart.get_value_projection(at(50), Some(&projection)), Some(6.0));
+  }
+
+  #[test]
+  fn it_filters_by_value() {
+    // Leaves of three points: a spike, a dip, and then a whole leaf of outliers
+    let chart = ten_second_chart(&[1.0, 90.0, 3.0, 4.0, -80.0, 6.0, 99.0, 99.0, 99.0, 10.0]);
+
+    let projection = chart.new_projection(at(0), at(90), vec![
+      Box::new(ProjectionFilter::at_most(50.0)),
+    ]);
+    assert_eq!(chart.get_value_projection(at(10), Some(&projection)), Some(2.0));
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), Some(-80.0));
+
+    let projection = chart.new_projection(at(0), at(90), vec![
+      Box::new(ProjectionFilter::at_least(0.0)),
+    ]);
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), Some(5.0));
+
+    // Whole leaves of outliers are skipped over
+    let projection = chart.new_projection(at(0), at(90), vec![
+      Box::new(ProjectionFilter::within(0.0, 50.0)),
+    ]);
+    assert_eq!(chart.get_value_projection(at(10), Some(&projection)), Some(2.0));
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), Some(5.0));
+    assert_eq!(chart.get_value_projection(at(70), Some(&projection)), Some(8.0));
+
+    // Filters and maps run in order
+    let projection = chart.new_projection(at(0), at(90), vec![
+      Box::new(ProjectionOperation::new(|point| Point::new(point.value / 10.0, point.timestamp))),
+      Box::new(ProjectionFilter::new(|point| point.value.abs() < 5.0)),
+    ]);
+    assert_eq!(chart.get_value_projection(at(10), Some(&projection)), Some(0.2));
+    assert_eq!(chart.get_value_projection(at(70), Some(&projection)), Some(0.8));
+  }
+
+  #[test]
+  fn it_trims_leaves_at_the_edges_of_the_projection() {
+    // Leaves from 0 to 20, 30 to 50 and 60 to 80 seconds
+    let chart = ten_second_chart(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+    let projection = chart.new_projection(at(20), at(60), vec![]);
+    assert_eq!(chart.get_value_projection(at(20), Some(&projection)), Some(3.0));
+    assert_eq!(chart.get_value_projection(at(25), Some(&projection)), Some(3.5));
+    assert_eq!(chart.get_value_projection(at(55), Some(&projection)), Some(6.5));
+    assert_eq!(chart.get_value_projection(at(60), Some(&projection)), Some(7.0));
+
+    // The points outside of the projection are gone
+    assert_eq!(chart.get_value_projection(at(15), Some(&projection)), None);
+    assert_eq!(chart.get_value_projection(at(65), Some(&projection)), None);
+
+    // Operations only see the points inside the projection
+    let projection = chart.new_projection(at(15), at(65), vec![
+      Box::new(ProjectionFilter::new(|point| point.value < 2.0 || point.value > 8.0)),
+    ]);
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), None);
+  }
+
+  #[test]
+  fn it_only_projects_leaves_within_the_range_of_the_projection() {
+    // Ten leaves of three points, from 0 to 290 seconds
+    let values: Vec<f64> = (0..30).map(|value| value as f64).collect();
+    let chart = ten_second_chart(&values);
+
+    // Deleting every leaf means looking for points never succeeds, so only the edges of the
+    // projection's range stop the search
+    let projection = chart.new_projection(at(100), at(150), vec![
+      Box::new(|_: &PointIndex| ProjectionOperationResult::Delete),
+    ]);
+    assert_eq!(chart.get_value_projection(at(125), Some(&projection)), None);
+
+    // Only the leaves from 90 to 110, 120 to 140 and 150 to 170 seconds were looked at
+    let first_points: Vec<f64> = projection.disposable.borrow().leaves.keys()
+      .map(|&node_index| chart.index[node_index].data.as_ref().unwrap()[0].value)
+      .collect();
+    assert_eq!(first_points.len(), 3);
+    assert!(first_points.iter().all(|&value| value >= 9.0 && value <= 15.0));
+  }
+
+  #[test]
+  fn it_shifts_and_scales_time() {
+    let chart = ten_second_chart(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+
+    // Last week's values, lined up with this week's
+    let week = Duration::weeks(1);
+    let projection = chart.new_projection(at(0), at(80), vec![
+      Box::new(ProjectionTimeShift::shift(week)),
+    ]);
+    for seconds in 0..80 {
+      let value = chart.get_value_projection(at(seconds) + week, Some(&projection));
+      assert_eq!(value, chart.get_value(at(seconds)));
+    }
+    assert_eq!(chart.get_value_projection(at(20), Some(&projection)), None);
+
+    // Twice as slow, starting a minute later
+    let projection = chart.new_projection(at(0), at(80), vec![
+      Box::new(ProjectionTimeShift::scale(at(0), 2.0)),
+      Box::new(ProjectionTimeShift::shift(Duration::minutes(1))),
+    ]);
+    assert_eq!(chart.get_value_projection(at(60), Some(&projection)), Some(1.0));
+    assert_eq!(chart.get_value_projection(at(80), Some(&projection)), Some(2.0));
+    assert_eq!(chart.get_value_projection(at(150), Some(&projection)), Some(5.5));
+    assert_eq!(chart.get_value_projection(at(220), Some(&projection)), Some(9.0));
+    assert_eq!(chart.get_value_projection(at(230), Some(&projection)), None);
+  }
+
+  #[test]
+  fn it_materializes_a_projection_into_a_new_chart() {
+    let chart = ten_second_chart(&[1.0, 2.0, f64::NAN, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0]);
+    let projection = chart.new_projection(at(10), at(70), vec![
+      Box::new(ProjectionFilter::without_nan()),
+      Box::new(ProjectionOperation::new(|point| Point::new(point.value * 2.0, point.timestamp))),
+      Box::new(ProjectionTimeShift::shift(Duration::minutes(1))),
+    ]);
+
+    let materialized = chart.materialize(&projection);
+    assert_eq!(materialized.points, vec![
+      Point::new(4.0, at(70)),
+      Point::new(8.0, at(90)),
+      Point::new(10.0, at(100)),
+      Point::new(12.0, at(110)),
+      Point::new(14.0, at(120)),
+      Point::new(16.0, at(130)),
+    ]);
+    assert_eq!(materialized.max_index_node_capacity, chart.max_index_node_capacity);
+    assert_eq!(materialized.interpolation, chart.interpolation);
+
+    // The new chart has its own index, with the same values as the projection
+    for seconds in 60..140 {
+      let value = materialized.get_value(at(seconds));
+      assert_eq!(value, chart.get_value_projection(at(seconds), Some(&projection)));
+    }
+
+    let projection = chart.new_projection(at(200), at(300), vec![]);
+    assert_eq!(chart.materialize(&projection).points, vec![]);
+  }
+
+  #[test]
+  fn it_caches_projected_leaves_until_the_chart_changes() {
+    // Leaves from 0 to 20, 30 to 50 and 60 to 80 seconds
+    let mut chart = ten_second_chart(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+
+    // Count how many times a leaf is projected
+    let applied = Rc::new(Cell::new(0));
+    let counter = applied.clone();
+    let mut projection = chart.new_projection(at(0), at(80), vec![
+      Box::new(move |index_node: &PointIndex| {
+        counter.set(counter.get() + 1);
+        let data = index_node.data.as_ref().unwrap();
+        let scaled = data.iter().map(|point| Point::new(point.value * 10.0, point.timestamp));
+        ProjectionOperationResult::Replace(Box::new(index_node.with_data(scaled.collect())))
+      }),
+    ]);
+
+    for _ in 0..100 {
+      assert_eq!(chart.get_value_projection(at(25), Some(&projection)), Some(35.0));
+      assert_eq!(chart.get_value_projection(at(45), Some(&projection)), Some(55.0));
+    }
+    assert_eq!(applied.get(), 2);
+
+    // Changing the chart throws the cache away
+    chart.insert(Point::new(100.0, at(45)));
+    assert_eq!(chart.get_value_projection(at(45), Some(&projection)), Some(1000.0));
+    assert!(applied.get() > 2);
+
+    // Only so many leaves are kept
+    projection.cache_capacity = 1;
+    applied.set(0);
+    for _ in 0..10 {
+      assert_eq!(chart.get_value_projection(at(0), Some(&projection)), Some(10.0));
+      assert_eq!(chart.get_value_projection(at(80), Some(&projection)), Some(90.0));
+    }
+    assert_eq!(applied.get(), 20);
+    let disposable = projection.disposable.borrow();
+    assert_eq!(disposable.leaves.len(), 1);
+    assert_eq!(disposable.nodes.len() - disposable.free.len(), 1);
+  }
+
+  #[test]
+  fn it_forgets_cached_leaves_of_a_chart_that_was_replaced() {
+    let mut chart = ten_second_chart(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+    let projection = chart.new_projection(at(0), at(80), vec![
+      Box::new(|index_node: &PointIndex| {
+        let data = index_node.data.as_ref().unwrap();
+        let scaled = data.iter().map(|point| Point::new(point.value * 10.0, point.timestamp));
+        ProjectionOperationResult::Replace(Box::new(index_node.with_data(scaled.collect())))
+      }),
+    ]);
+    assert_eq!(chart.get_value_projection(at(20), Some(&projection)), Some(30.0));
+
+    // The new chart lives where the old one did, but has none of its leaves
+    chart = chart.with_points(chart.points.iter()
+      .map(|point| Point::new(point.value * 100.0, point.timestamp))
+      .collect());
+    assert_eq!(chart.get_value_projection(at(20), Some(&projection)), Some(3000.0));
+  }
+
+  #[test]
+  fn it_forgets_cached_leaves_when_the_range_changes() {
+    let chart = ten_second_chart(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+    let mut projection = chart.new_projection(at(0), at(80), vec![]);
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), Some(5.0));
+
+    projection.set_range(at(50), at(80));
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), None);
+    assert_eq!(chart.get_value_projection(at(50), Some(&projection)), Some(6.0));
+
+    projection.set_range(at(0), at(40));
+    assert_eq!(chart.get_value_projection(at(40), Some(&projection)), Some(5.0));
+    assert_eq!(chart.get_value_projection(at(50), Some(&projection)), None);
+  }
+
+  #[test]
+  fn it_chains_projections_into_a_pipeline() {
+    let celsius = [10.0, f64::NAN, 20.0, 30.0, 25.0, f64::NAN, 15.0, 5.0, 0.0, -10.0];
+    let chart = ten_second_chart(&celsius);
+
+    // Keep 10 to 70 seconds, then drop NaNs, then convert to Fahrenheit
+    let projection = chart.new_projection(at(10), at(70), vec![])
+      .then(chart.new_projection(at(0), at(90), vec![Box::new(ProjectionFilter::without_nan())]))
+      .then(chart.new_projection(at(0), at(90), vec![
+        Box::new(ProjectionOperation::new(|point| {
+          Point::new(point.value * 1.8 + 32.0, point.timestamp)
+        })),
+      ]));
+    assert_eq!(projection.start_time(), at(10));
+    assert_eq!(projection.end_time(), at(70));
+    assert_eq!(projection.operations().len(), 2);
+    assert_eq!(chart.materialize(&projection).points, vec![
+      Point::new(68.0, at(20)),
+      Point::new(86.0, at(30)),
+      Point::new(77.0, at(40)),
+      Point::new(59.0, at(60)),
+      Point::new(41.0, at(70)),
+    ]);
+    assert_eq!(chart.get_value_projection(at(50), Some(&projection)), Some(68.0));
+    assert_eq!(chart.get_value_projection(at(80), Some(&projection)), None);
+
+    // Later stages see the timestamps that come out of earlier ones, so a range after a shift is of
+    // the shifted timestamps
+    let projection = chart.new_projection(at(0), at(90), vec![
+      Box::new(ProjectionTimeShift::shift(Duration::minutes(1))),
+    ]).then(chart.new_projection(at(60), at(100), vec![]));
+    assert_eq!(projection.start_time(), at(0));
+    assert_eq!(projection.end_time(), at(40));
+    assert_eq!(chart.get_value_projection(at(100), Some(&projection)), Some(25.0));
+    assert_eq!(chart.get_value_projection(at(110), Some(&projection)), None);
 
-    // By default, return the accumulator and the projection/disposable combo
-    (accumulator, projection_disposable)
+    // Stages that don't overlap leave nothing
+    let projection = chart.new_projection(at(0), at(20), vec![])
+      .then(chart.new_projection(at(50), at(90), vec![]));
+    assert_eq!(chart.materialize(&projection).points, vec![]);
   }
 }
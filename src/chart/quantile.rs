@@ -0,0 +1,312 @@
+extern crate chrono;
+use chrono::{DateTime, Utc};
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use chart::chart::Chart;
+
+// How far off a quantile found with a sketch can be, relative to the value found.
+pub const SKETCH_RELATIVE_ACCURACY: f64 = 0.01;
+
+// Ranges with up to this many points have their quantiles worked out exactly, by sorting them.
+const EXACT_QUANTILE_LIMIT: usize = 1000;
+
+// A sketch of the values in a set of points, that can find any quantile of them to within
+// `SKETCH_RELATIVE_ACCURACY` without keeping the values themselves (DDSketch). Values are counted
+// in buckets that grow exponentially wider the further they are from zero. Two sketches can be
+// merged by adding up their buckets, which gives exactly the same sketch as adding all of the
+// values to one.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct QuantileSketch {
+  // The number of values in each bucket, above and below zero. Bucket `i` holds the values whose
+  // size is between `gamma^(i-1)` and `gamma^i`.
+  positive: BTreeMap<i32, u64>,
+  negative: BTreeMap<i32, u64>,
+
+  // Values too close to zero to be bucketed.
+  zeros: u64,
+
+  pub count: u64,
+}
+
+// How much wider each bucket is than the one before it.
+fn gamma() -> f64 {
+  (1.0 + SKETCH_RELATIVE_ACCURACY) / (1.0 - SKETCH_RELATIVE_ACCURACY)
+}
+
+// The value that stands in for every value in a bucket: the one that's the same relative distance
+// from both edges of the bucket.
+fn bucket_value(bucket: i32) -> f64 {
+  2.0 * gamma().powi(bucket) / (gamma() + 1.0)
+}
+
+impl QuantileSketch {
+  pub fn new() -> QuantileSketch {
+    QuantileSketch {
+      positive: BTreeMap::new(),
+      negative: BTreeMap::new(),
+      zeros: 0,
+      count: 0,
+    }
+  }
+
+  // Add a value to the sketch. NaN values are left out.
+  pub fn add(&mut self, value: f64) {
+    if value.is_nan() {
+      return;
+    }
+
+    self.count += 1;
+    if value.abs() < f64::MIN_POSITIVE {
+      self.zeros += 1;
+      return;
+    }
+    let bucket = (value.abs().ln() / gamma().ln()).ceil() as i32;
+    let buckets = if value > 0.0 { &mut self.positive } else { &mut self.negative };
+    *buckets.entry(bucket).or_insert(0) += 1;
+  }
+
+  pub fn merge(&mut self, other: &QuantileSketch) {
+    for (&bucket, &count) in &other.positive {
+      *self.positive.entry(bucket).or_insert(0) += count;
+    }
+    for (&bucket, &count) in &other.negative {
+      *self.negative.entry(bucket).or_insert(0) += count;
+    }
+    self.zeros += other.zeros;
+    self.count += other.count;
+  }
+
+  // Find the `q` quantile of the values in the sketch, where `q` is from zero (the smallest value)
+  // to one (the largest).
+  pub fn quantile(&self, q: f64) -> Option<f64> {
+    if self.count == 0 {
+      return None;
+    }
+
+    // Walk the buckets from the most negative values to the most positive, until reaching the one
+    // holding the value with the right rank.
+    let rank = (q * (self.count - 1) as f64).floor() as u64;
+    let mut seen = 0;
+    for (&bucket, &count) in self.negative.iter().rev() {
+      seen += count;
+      if seen > rank {
+        return Some(-bucket_value(bucket));
+      }
+    }
+    seen += self.zeros;
+    if seen > rank {
+      return Some(0.0);
+    }
+    for (&bucket, &count) in &self.positive {
+      seen += count;
+      if seen > rank {
+        return Some(bucket_value(bucket));
+      }
+    }
+    None
+  }
+}
+
+// Find the `q` quantile of `values`, interpolating between the two closest values when it falls
+// between them. `values` must already be sorted.
+fn exact_quantile(values: &[f64], q: f64) -> Option<f64> {
+  if values.is_empty() {
+    return None;
+  }
+
+  let position = q * (values.len() - 1) as f64;
+  let below = position.floor() as usize;
+  let above = position.ceil() as usize;
+  Some(values[below] + (values[above] - values[below]) * (position - below as f64))
+}
+
+impl Chart {
+  // Find the `q` quantile (from zero for the smallest value to one for the largest) of the values
+  // of the points from `start` up to (but not including) `end`. NaN values are left out. Small
+  // ranges are worked out exactly. Larger ones are found from the sketches cached in the index, to
+  // within `SKETCH_RELATIVE_ACCURACY` of the value.
+  pub fn quantile(&self, start: DateTime<Utc>, end: DateTime<Utc>, q: f64) -> Option<f64> {
+    debug!("CALLING chart.quantile({:?}, {:?}, {})", start, end, q);
+    if !(0.0..=1.0).contains(&q) {
+      panic!("Quantiles have to be between zero and one, got {}!", q);
+    }
+
+    let summary = self.summarize(start, end);
+    if summary.count > EXACT_QUANTILE_LIMIT {
+      let mut sketch = QuantileSketch::new();
+      self.sketch_index_node_between(0, start, end, &mut sketch);
+      return sketch.quantile(q);
+    }
+
+    let mut values: Vec<f64> = self.range(Bound::Included(start), Bound::Excluded(end))
+      .map(|point| point.value)
+      .filter(|value| !value.is_nan())
+      .collect();
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    exact_quantile(&values, q)
+  }
+
+  // Add the values of the points under the node at `node_index` from `start` up to (but not
+  // including) `end` to `sketch`, using the sketches of whole nodes where possible.
+  fn sketch_index_node_between(
+    &self,
+    node_index: usize,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    sketch: &mut QuantileSketch,
+  ) {
+    let node = &self.index[node_index];
+    let (first, last) = match node.summary.time_span() {
+      Some(time_span) => time_span,
+      None => return,
+    };
+    if last < start || first >= end {
+      return;
+    }
+    if first >= start && last < end {
+      self.merge_sketch_of_index_node(node_index, sketch);
+      return;
+    }
+
+    match node.data {
+      Some(ref data) => {
+        for point in data {
+          if point.timestamp >= start && point.timestamp < end {
+            sketch.add(point.value);
+          }
+        }
+      },
+      None => {
+        for &child_index in &node.children {
+          self.sketch_index_node_between(child_index, start, end, sketch);
+        }
+      },
+    }
+  }
+
+  // Add every value under the node at `node_index` to `sketch`. The values of a leaf are added one
+  // by one, while an inner node's sketch is built from its children the first time it's needed and
+  // kept for next time.
+  fn merge_sketch_of_index_node(&self, node_index: usize, sketch: &mut QuantileSketch) {
+    let node = &self.index[node_index];
+    if let Some(ref data) = node.data {
+      for point in data {
+        sketch.add(point.value);
+      }
+      return;
+    }
+
+    let node_sketch = node.sketch.get_or_init(|| {
+      let mut node_sketch = QuantileSketch::new();
+      for &child_index in &node.children {
+        self.merge_sketch_of_index_node(child_index, &mut node_sketch);
+      }
+      node_sketch
+    });
+    sketch.merge(node_sketch);
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+  use std::thread;
+  use chart::point::Point;
+  use chart::quantile::{QuantileSketch, SKETCH_RELATIVE_ACCURACY};
+  use chart::test_helpers::{at, chart_with_a_point_every};
+
+  #[test]
+  fn it_finds_exact_quantiles_of_small_ranges() {
+    let values: Vec<f64> = (1..101).rev().map(|value| value as f64).collect();
+    let chart = chart_with_a_point_every(Duration::seconds(1), values.iter().cloned(), 16);
+
+    assert_eq!(chart.quantile(at(0), at(100), 0.0), Some(1.0));
+    assert_eq!(chart.quantile(at(0), at(100), 0.5), Some(50.5));
+    assert_eq!(chart.quantile(at(0), at(100), 1.0), Some(100.0));
+    assert!((chart.quantile(at(0), at(100), 0.95).unwrap() - 95.05).abs() < 1e-9);
+
+    // The 10 points from 90 down to 81
+    assert_eq!(chart.quantile(at(10), at(20), 0.5), Some(85.5));
+    assert_eq!(chart.quantile(at(200), at(300), 0.5), None);
+  }
+
+  #[test]
+  fn it_finds_quantiles_of_large_ranges_from_sketches() {
+    let mut seed = 11u64;
+    let values: Vec<f64> = (0..20000)
+      .map(|_| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((seed >> 33) % 100_000) as f64 / 100.0 - 200.0
+      })
+      .collect();
+    let chart = chart_with_a_point_every(Duration::seconds(1), values.iter().cloned(), 16);
+
+    for &(start, end) in &[(0, 20000), (1234, 15678)] {
+      let mut sorted = values[start..end].to_vec();
+      sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+      for &q in &[0.0, 0.01, 0.25, 0.5, 0.95, 0.99, 1.0] {
+        let expected = sorted[(q * (sorted.len() - 1) as f64).floor() as usize];
+        let quantile = chart.quantile(at(start as i64), at(end as i64), q).unwrap();
+        assert!(
+          (quantile - expected).abs() <= SKETCH_RELATIVE_ACCURACY * expected.abs() + 1e-9,
+          "{} quantile of {}..{} is {}, expected {}", q, start, end, quantile, expected,
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn it_rebuilds_sketches_after_the_chart_changes() {
+    let values: Vec<f64> = (0..5000).map(|value| value as f64).collect();
+    let mut chart = chart_with_a_point_every(Duration::seconds(1), values.iter().cloned(), 16);
+    let median = chart.quantile(at(0), at(5000), 0.5).unwrap();
+    assert!((median - 2500.0).abs() <= SKETCH_RELATIVE_ACCURACY * 2500.0 + 1e-9);
+
+    for seconds in 5000..10000 {
+      chart.insert(Point::new(100_000.0, at(seconds)));
+    }
+    let quantile = chart.quantile(at(0), at(10000), 0.75).unwrap();
+    assert!((quantile - 100_000.0).abs() <= SKETCH_RELATIVE_ACCURACY * 100_000.0 + 1e-9);
+  }
+
+  #[test]
+  fn it_finds_quantiles_from_several_threads_at_once() {
+    let values: Vec<f64> = (0..5000).map(|value| value as f64).collect();
+    let chart = chart_with_a_point_every(Duration::seconds(1), values.iter().cloned(), 16);
+    let medians: Vec<f64> = thread::scope(|scope| {
+      let threads: Vec<_> = (0..4)
+        .map(|_| scope.spawn(|| chart.quantile(at(0), at(5000), 0.5).unwrap()))
+        .collect();
+      threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+    });
+    for median in medians {
+      assert!((median - 2500.0).abs() <= SKETCH_RELATIVE_ACCURACY * 2500.0 + 1e-9);
+    }
+
+    // The sketches built along the way don't make the index any different
+    let fresh = chart_with_a_point_every(Duration::seconds(1), values.iter().cloned(), 16);
+    assert_eq!(chart.index, fresh.index);
+  }
+
+  #[test]
+  fn it_merges_sketches() {
+    let mut all = QuantileSketch::new();
+    let mut first = QuantileSketch::new();
+    let mut second = QuantileSketch::new();
+    for value in -500..500 {
+      let value = value as f64 * 0.37;
+      all.add(value);
+      if value < 20.0 { first.add(value) } else { second.add(value) }
+    }
+    first.merge(&second);
+    assert_eq!(first, all);
+    let smallest = all.quantile(0.0).unwrap();
+    assert!((smallest + 185.0).abs() <= 185.0 * SKETCH_RELATIVE_ACCURACY);
+    assert_eq!(QuantileSketch::new().quantile(0.5), None);
+  }
+}
@@ -0,0 +1,248 @@
+extern crate chrono;
+use chrono::{DateTime, Utc};
+
+use std::ops::Bound;
+
+use chart::chart::Chart;
+use chart::point::Point;
+
+// A position in the index: a leaf, and the position of a point within that leaf.
+type Cursor = (usize, usize);
+
+// An iterator over the points of a chart between two timestamps, in timestamp order. It's created
+// with `Chart::range`, and walks from leaf to leaf through the index rather than scanning `points`.
+pub struct PointRange<'a> {
+  chart: &'a Chart,
+
+  // The next point to return from either end. Once these meet and that point has been returned,
+  // the range is done.
+  front: Cursor,
+  back: Cursor,
+  done: bool,
+}
+
+impl<'a> PointRange<'a> {
+  fn point_at(&self, cursor: Cursor) -> &'a Point {
+    let chart: &'a Chart = self.chart;
+    &chart.index[cursor.0].data.as_ref().unwrap()[cursor.1]
+  }
+}
+
+impl<'a> Iterator for PointRange<'a> {
+  type Item = &'a Point;
+
+  fn next(&mut self) -> Option<&'a Point> {
+    if self.done {
+      return None;
+    }
+
+    let point = self.point_at(self.front);
+    if self.front == self.back {
+      self.done = true;
+    } else {
+      self.front = self.chart.cursor_after(self.front).unwrap();
+    }
+    Some(point)
+  }
+}
+
+impl<'a> DoubleEndedIterator for PointRange<'a> {
+  fn next_back(&mut self) -> Option<&'a Point> {
+    if self.done {
+      return None;
+    }
+
+    let point = self.point_at(self.back);
+    if self.front == self.back {
+      self.done = true;
+    } else {
+      self.back = self.chart.cursor_before(self.back).unwrap();
+    }
+    Some(point)
+  }
+}
+
+impl Chart {
+  // Return an iterator over the points between `start` and `end`, in timestamp order. Either
+  // bound can be inclusive, exclusive or unbounded. To walk backwards from `end` to `start`
+  // instead, reverse the iterator with `.rev()`.
+  pub fn range(&self, start: Bound<DateTime<Utc>>, end: Bound<DateTime<Utc>>) -> PointRange<'_> {
+    debug!("CALLING chart.range({:?}, {:?})", start, end);
+    let front = match start {
+      Bound::Included(timestamp) => {
+        let node_index = self.lookup_first_in_index(timestamp).unwrap();
+        self.first_cursor_from(node_index, |point| point.timestamp >= timestamp)
+      },
+      Bound::Excluded(timestamp) => {
+        let node_index = self.lookup_in_index(timestamp).unwrap();
+        self.first_cursor_from(node_index, |point| point.timestamp > timestamp)
+      },
+      Bound::Unbounded => self.first_cursor_from(self.first_leaf_in_index(), |_| true),
+    };
+    let back = match end {
+      Bound::Included(timestamp) => {
+        let node_index = self.lookup_in_index(timestamp).unwrap();
+        self.last_cursor_from(node_index, |point| point.timestamp <= timestamp)
+      },
+      Bound::Excluded(timestamp) => {
+        let node_index = self.lookup_first_in_index(timestamp).unwrap();
+        self.last_cursor_from(node_index, |point| point.timestamp < timestamp)
+      },
+      Bound::Unbounded => self.last_cursor_from(self.last_leaf_in_index(), |_| true),
+    };
+
+    match (front, back) {
+      (Some(front), Some(back)) => {
+        // If the first point after `start` is past the last point before `end`, there's nothing
+        // in between them.
+        let front_timestamp = self.index[front.0].data.as_ref().unwrap()[front.1].timestamp;
+        let back_timestamp = self.index[back.0].data.as_ref().unwrap()[back.1].timestamp;
+        PointRange { chart: self, front: front, back: back, done: front_timestamp > back_timestamp }
+      },
+      _ => PointRange { chart: self, front: (0, 0), back: (0, 0), done: true },
+    }
+  }
+
+  // The first point in the leaf at `node_index` (or failing that, the leaf after it) for which
+  // `predicate` is true. Points are expected to go from false to true in timestamp order.
+  fn first_cursor_from<F>(&self, node_index: usize, predicate: F) -> Option<Cursor>
+    where F: Fn(&Point) -> bool
+  {
+    let data = self.index[node_index].data.as_ref().unwrap();
+    match data.iter().position(&predicate) {
+      Some(position) => Some((node_index, position)),
+      None => self.index[node_index].more.and_then(|more_index| {
+        let more_data = self.index[more_index].data.as_ref().unwrap();
+        more_data.iter().position(&predicate).map(|position| (more_index, position))
+      }),
+    }
+  }
+
+  // The last point in the leaf at `node_index` (or failing that, the leaf before it) for which
+  // `predicate` is true. Points are expected to go from true to false in timestamp order.
+  fn last_cursor_from<F>(&self, node_index: usize, predicate: F) -> Option<Cursor>
+    where F: Fn(&Point) -> bool
+  {
+    let data = self.index[node_index].data.as_ref().unwrap();
+    match data.iter().rposition(&predicate) {
+      Some(position) => Some((node_index, position)),
+      None => self.index[node_index].less.and_then(|less_index| {
+        let less_data = self.index[less_index].data.as_ref().unwrap();
+        less_data.iter().rposition(&predicate).map(|position| (less_index, position))
+      }),
+    }
+  }
+
+  // Step forwards or backwards by one point, moving on to the neighboring leaf if needed.
+  fn cursor_after(&self, (node_index, position): Cursor) -> Option<Cursor> {
+    if position+1 < self.index[node_index].len() {
+      Some((node_index, position+1))
+    } else {
+      self.index[node_index].more.map(|more_index| (more_index, 0))
+    }
+  }
+  fn cursor_before(&self, (node_index, position): Cursor) -> Option<Cursor> {
+    if position > 0 {
+      Some((node_index, position-1))
+    } else {
+      self.index[node_index].less.map(|less_index| (less_index, self.index[less_index].len()-1))
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::{Duration, Utc, TimeZone};
+  use std::ops::Bound::{Excluded, Included, Unbounded};
+  use chart::point::Point;
+  use chart::test_helpers::chart_counting_up_every_minute;
+
+  fn values<'a, I: Iterator<Item=&'a Point>>(points: I) -> Vec<f64> {
+    points.map(|point| point.value).collect()
+  }
+
+  #[test]
+  fn it_iterates_over_ranges_of_points() {
+    let chart = chart_counting_up_every_minute(0, 60);
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 10, 0);
+    let end = Utc.ymd(2018, 1, 1).and_hms(9, 20, 0);
+
+    assert_eq!(
+      values(chart.range(Included(start), Included(end))),
+      (10..21).map(|i| i as f64).collect::<Vec<_>>()
+    );
+    assert_eq!(
+      values(chart.range(Excluded(start), Excluded(end))),
+      (11..20).map(|i| i as f64).collect::<Vec<_>>()
+    );
+
+    // Bounds in between points
+    assert_eq!(
+      values(chart.range(
+        Included(start + Duration::seconds(30)),
+        Excluded(end - Duration::seconds(30)),
+      )),
+      (11..20).map(|i| i as f64).collect::<Vec<_>>()
+    );
+
+    // Unbounded ends
+    assert_eq!(values(chart.range(Unbounded, Excluded(start))).len(), 10);
+    assert_eq!(values(chart.range(Excluded(end), Unbounded)).len(), 39);
+    assert_eq!(values(chart.range(Unbounded, Unbounded)).len(), 60);
+  }
+
+  #[test]
+  fn it_iterates_over_ranges_of_points_in_reverse() {
+    let chart = chart_counting_up_every_minute(0, 60);
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 10, 0);
+    let end = Utc.ymd(2018, 1, 1).and_hms(9, 20, 0);
+
+    assert_eq!(
+      values(chart.range(Included(start), Excluded(end)).rev()),
+      (10..20).rev().map(|i| i as f64).collect::<Vec<_>>()
+    );
+
+    // Taking from both ends meets in the middle without repeating a point
+    let mut range = chart.range(Included(start), Included(start + Duration::minutes(2)));
+    assert_eq!(range.next().map(|point| point.value), Some(10.0));
+    assert_eq!(range.next_back().map(|point| point.value), Some(12.0));
+    assert_eq!(range.next_back().map(|point| point.value), Some(11.0));
+    assert_eq!(range.next(), None);
+    assert_eq!(range.next_back(), None);
+  }
+
+  #[test]
+  fn it_returns_nothing_for_empty_ranges() {
+    let chart = chart_counting_up_every_minute(0, 60);
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 10, 0);
+
+    assert_eq!(chart.range(Included(start), Excluded(start)).count(), 0);
+    assert_eq!(chart.range(Excluded(start), Included(start)).count(), 0);
+    let between_points = (
+      Included(start + Duration::seconds(10)),
+      Included(start + Duration::seconds(20)),
+    );
+    assert_eq!(chart.range(between_points.0, between_points.1).count(), 0);
+    assert_eq!(chart.range(Included(start + Duration::hours(1)), Unbounded).count(), 0);
+    assert_eq!(chart.range(Unbounded, Excluded(Utc.ymd(2018, 1, 1).and_hms(9, 0, 0))).count(), 0);
+    assert_eq!(chart.range(Included(start), Included(start - Duration::minutes(1))).count(), 0);
+
+    let empty_chart = chart_counting_up_every_minute(0, 0);
+    assert_eq!(empty_chart.range(Unbounded, Unbounded).count(), 0);
+    assert_eq!(empty_chart.range(Included(start), Included(start)).rev().count(), 0);
+  }
+
+  #[test]
+  fn it_includes_every_point_sharing_a_bound_timestamp() {
+    let timestamp = Utc.ymd(2018, 1, 1).and_hms(9, 10, 0);
+    let mut chart = chart_counting_up_every_minute(0, 20);
+    for value in 100..110 {
+      chart.insert(Point::new(value as f64, timestamp));
+    }
+
+    assert_eq!(chart.range(Included(timestamp), Included(timestamp)).count(), 11);
+    assert_eq!(chart.range(Excluded(timestamp), Unbounded).count(), 9);
+    assert_eq!(chart.range(Unbounded, Excluded(timestamp)).count(), 10);
+  }
+}
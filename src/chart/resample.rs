@@ -0,0 +1,170 @@
+extern crate chrono;
+use chrono::{DateTime, Duration, Utc};
+
+use std::collections::VecDeque;
+use std::ops::Bound;
+
+use chart::chart::Chart;
+use chart::interpolation::Interpolation;
+use chart::point::Point;
+
+impl Chart {
+  // Evaluate the chart every `step` from `start` up to and including `end`, returning a point for
+  // each timestamp that has a value. Rather than looking up every timestamp from the root of the
+  // index, this walks forwards through the leaves once, keeping the points on either side of the
+  // current timestamp as it goes.
+  pub fn resample(&self, start: DateTime<Utc>, end: DateTime<Utc>, step: Duration) -> Vec<Point> {
+    self.resample_with(start, end, step, self.interpolation)
+  }
+
+  // Like `resample`, but interpolating between points with `interpolation` rather than the chart's
+  // own interpolation.
+  pub fn resample_with(
+    &self,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    step: Duration,
+    interpolation: Interpolation,
+  ) -> Vec<Point> {
+    debug!("CALLING chart.resample_with({:?}, {:?}, {:?}, {:?})", start, end, step, interpolation);
+    if step <= Duration::zero() {
+      panic!("Resampling needs a step greater than zero, got {:?}!", step);
+    }
+
+    let count = self.points_needed_around(interpolation);
+    let mut resampled = vec![];
+
+    // The points before and at or after the current timestamp, each ordered from closest to
+    // furthest away, along with the points still to come.
+    let mut before: VecDeque<Point> = self.range(Bound::Unbounded, Bound::Excluded(start))
+      .rev()
+      .take(count)
+      .cloned()
+      .collect();
+    let mut after: VecDeque<Point> = VecDeque::new();
+    let mut upcoming = self.range(Bound::Included(start), Bound::Unbounded);
+
+    let mut timestamp = start;
+    while timestamp <= end {
+      loop {
+        // Keep `count` points ahead, and move any that are now behind the timestamp over.
+        while after.len() < count {
+          match upcoming.next() {
+            Some(point) => after.push_back(point.clone()),
+            None => break,
+          }
+        }
+        match after.front() {
+          Some(point) if point.timestamp < timestamp => {},
+          _ => break,
+        }
+        before.push_front(after.pop_front().unwrap());
+        before.truncate(count);
+      }
+
+      let value = self.interpolate_around(
+        timestamp,
+        before.make_contiguous(),
+        after.make_contiguous(),
+        interpolation,
+      );
+      if let Some(value) = value {
+        resampled.push(Point::new(value, timestamp));
+      }
+      timestamp = timestamp + step;
+    }
+
+    resampled
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::{Duration, Utc, TimeZone};
+  use chart::chart::Chart;
+  use chart::gap_policy::{Extrapolation, GapPolicy};
+  use chart::interpolation::Interpolation;
+  use chart::test_helpers::chart_with_a_point_every;
+
+  // A chart with a point every seven seconds from 9:00, going up and down in a sawtooth.
+  fn sawtooth_chart(length: usize, interpolation: Interpolation) -> Chart {
+    let values = (0..length).map(|i| (i % 5) as f64);
+    let mut chart = chart_with_a_point_every(Duration::seconds(7), values, 3);
+    chart.interpolation = interpolation;
+    chart
+  }
+
+  #[test]
+  fn it_resamples_onto_a_fixed_interval_grid() {
+    let chart = sawtooth_chart(100, Interpolation::Linear);
+    let start = Utc.ymd(2018, 1, 1).and_hms(9, 1, 0);
+    let end = Utc.ymd(2018, 1, 1).and_hms(9, 5, 0);
+
+    let resampled = chart.resample(start, end, Duration::seconds(10));
+    assert_eq!(resampled.len(), 25);
+    assert_eq!(resampled[0].timestamp, start);
+    assert_eq!(resampled[24].timestamp, end);
+    for point in &resampled {
+      assert_eq!(Some(point.value), chart.get_value(point.timestamp));
+    }
+
+    // A step that doesn't land on `end` stops before it
+    let resampled = chart.resample(start, end, Duration::seconds(7));
+    assert_eq!(resampled.len(), 35);
+    assert_eq!(resampled[34].timestamp, start + Duration::seconds(7 * 34));
+  }
+
+  #[test]
+  fn it_resamples_with_the_interpolation_of_the_chart() {
+    let start = Utc.ymd(2018, 1, 1).and_hms(8, 59, 0);
+    let end = Utc.ymd(2018, 1, 1).and_hms(9, 12, 0);
+    for &interpolation in &[
+      Interpolation::Previous,
+      Interpolation::Next,
+      Interpolation::Nearest,
+      Interpolation::Linear,
+      Interpolation::MonotoneCubic,
+    ] {
+      let chart = sawtooth_chart(100, interpolation);
+      for point in chart.resample(start, end, Duration::milliseconds(2500)) {
+        assert_eq!(
+          Some(point.value), chart.get_value(point.timestamp),
+          "mismatch at {:?} ({:?})", point.timestamp, interpolation,
+        );
+      }
+
+      // Overriding the interpolation
+      let step = Duration::seconds(3);
+      for point in chart.resample_with(start, end, step, Interpolation::Previous) {
+        let value = chart.get_value_with(point.timestamp, Interpolation::Previous);
+        assert_eq!(Some(point.value), value);
+      }
+    }
+  }
+
+  #[test]
+  fn it_leaves_out_timestamps_without_a_value() {
+    let mut chart = sawtooth_chart(10, Interpolation::Linear);
+    let start = Utc.ymd(2018, 1, 1).and_hms(8, 59, 0);
+    let end = Utc.ymd(2018, 1, 1).and_hms(9, 3, 0);
+
+    // The chart runs from 9:00:00 to 9:01:03
+    let resampled = chart.resample(start, end, Duration::seconds(1));
+    assert_eq!(resampled.len(), 64);
+    assert_eq!(resampled[0].timestamp, Utc.ymd(2018, 1, 1).and_hms(9, 0, 0));
+
+    chart.gap_policy = GapPolicy {
+      before_start: Extrapolation::Hold,
+      after_end: Extrapolation::Hold,
+      ..GapPolicy::default()
+    };
+    let resampled = chart.resample(start, end, Duration::seconds(1));
+    assert_eq!(resampled.len(), 241);
+    assert_eq!(resampled[0].value, 0.0);
+    assert_eq!(resampled[240].value, 4.0);
+
+    let empty_chart = sawtooth_chart(0, Interpolation::Linear);
+    assert_eq!(empty_chart.resample(start, end, Duration::seconds(1)), vec![]);
+  }
+}
@@ -0,0 +1,347 @@
+extern crate chrono;
+use chrono::Duration;
+
+use std::collections::VecDeque;
+
+use chart::chart::{Chart, duration_in_nanoseconds, nanoseconds_in};
+use chart::point::Point;
+
+// How far back from each point a rolling window reaches.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum Window {
+  // The point and the ones just before it, this many in all.
+  Points(usize),
+
+  // Every point in this much time leading up to the point, not including the start of the window.
+  Duration(Duration),
+}
+
+// What a rolling window works out from the points in it.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum Rolling {
+  Mean,
+  Min,
+  Max,
+  Sum,
+  Median,
+}
+
+// The values in a rolling window, kept in whichever ways `rolling` needs them so that sliding the
+// window along by a point is cheap:
+//
+// - A running sum of the finite values for the mean and sum, with Neumaier's compensation so that
+//   it doesn't drift over long charts, and counts of the NaNs and infinities. Those are kept out of
+//   the sum so that it recovers once they've left the window.
+// - The values that could still become the smallest (or largest) value as the window moves on, in
+//   the order they were added. Each is smaller (or larger) than the ones before it, so the first is
+//   the minimum (or maximum).
+// - The values in sorted order for the median.
+struct RollingWindow {
+  rolling: Rolling,
+  values: VecDeque<f64>,
+  sum: f64,
+  compensation: f64,
+  nans: usize,
+  positive_infinities: usize,
+  negative_infinities: usize,
+  extremes: VecDeque<f64>,
+  sorted: Vec<f64>,
+}
+
+impl RollingWindow {
+  fn new(rolling: Rolling) -> RollingWindow {
+    RollingWindow {
+      rolling: rolling,
+      values: VecDeque::new(),
+      sum: 0.0,
+      compensation: 0.0,
+      nans: 0,
+      positive_infinities: 0,
+      negative_infinities: 0,
+      extremes: VecDeque::new(),
+      sorted: vec![],
+    }
+  }
+
+  fn len(&self) -> usize {
+    self.values.len()
+  }
+
+  // Add `value` to the running sum, or take it away again when `leaving` the window. Values that
+  // aren't finite are only counted.
+  fn change_sum(&mut self, value: f64, leaving: bool) {
+    if value.is_finite() {
+      let value = if leaving { -value } else { value };
+      let total = self.sum + value;
+      if self.sum.abs() >= value.abs() {
+        self.compensation += (self.sum - total) + value;
+      } else {
+        self.compensation += (value - total) + self.sum;
+      }
+      self.sum = total;
+      return;
+    }
+
+    let count = if value.is_nan() {
+      &mut self.nans
+    } else if value > 0.0 {
+      &mut self.positive_infinities
+    } else {
+      &mut self.negative_infinities
+    };
+    if leaving { *count -= 1 } else { *count += 1 }
+  }
+
+  fn sum(&self) -> f64 {
+    if self.nans > 0 || (self.positive_infinities > 0 && self.negative_infinities > 0) {
+      f64::NAN
+    } else if self.positive_infinities > 0 {
+      f64::INFINITY
+    } else if self.negative_infinities > 0 {
+      f64::NEG_INFINITY
+    } else {
+      self.sum + self.compensation
+    }
+  }
+
+  // Whether `a` should stay in `extremes` ahead of `b`.
+  fn is_more_extreme(&self, a: f64, b: f64) -> bool {
+    match self.rolling {
+      Rolling::Min => a <= b,
+      _ => a >= b,
+    }
+  }
+
+  // Add a value to the end of the window.
+  fn push(&mut self, value: f64) {
+    self.values.push_back(value);
+    match self.rolling {
+      Rolling::Mean | Rolling::Sum => self.change_sum(value, false),
+      Rolling::Min | Rolling::Max => {
+        while let Some(&last) = self.extremes.back() {
+          if self.is_more_extreme(last, value) {
+            break;
+          }
+          self.extremes.pop_back();
+        }
+        self.extremes.push_back(value);
+      },
+      Rolling::Median => {
+        let position = self.sorted.partition_point(|other| other.total_cmp(&value).is_lt());
+        self.sorted.insert(position, value);
+      },
+    }
+  }
+
+  // Take the first value off the start of the window.
+  fn pop(&mut self) {
+    let value = self.values.pop_front().unwrap();
+    match self.rolling {
+      Rolling::Mean | Rolling::Sum => self.change_sum(value, true),
+      Rolling::Min | Rolling::Max => {
+        if self.extremes.front().is_some_and(|&first| first.total_cmp(&value).is_eq()) {
+          self.extremes.pop_front();
+        }
+      },
+      Rolling::Median => {
+        let position = self.sorted.partition_point(|other| other.total_cmp(&value).is_lt());
+        self.sorted.remove(position);
+      },
+    }
+  }
+
+  fn value(&self) -> f64 {
+    match self.rolling {
+      Rolling::Mean => self.sum() / self.values.len() as f64,
+      Rolling::Sum => self.sum(),
+      Rolling::Min | Rolling::Max => self.extremes[0],
+      Rolling::Median => {
+        let middle = self.sorted.len() / 2;
+        if self.sorted.len().is_multiple_of(2) {
+          (self.sorted[middle-1] + self.sorted[middle]) / 2.0
+        } else {
+          self.sorted[middle]
+        }
+      },
+    }
+  }
+}
+
+impl Chart {
+  // A chart of `rolling` over the `window` leading up to each point. The result has a point at the
+  // timestamp of each point in this chart, except that windows of a number of points only start
+  // once there are that many points to fill them.
+  pub fn rolling(&self, window: Window, rolling: Rolling) -> Chart {
+    debug!("CALLING chart.rolling({:?}, {:?})", window, rolling);
+    match window {
+      Window::Points(0) => panic!("Rolling windows need at least one point!"),
+      Window::Duration(duration) if duration <= Duration::zero() => {
+        panic!("Rolling windows need a duration greater than zero, got {:?}!", duration);
+      },
+      _ => {},
+    }
+
+    let mut points = vec![];
+    let mut rolling_window = RollingWindow::new(rolling);
+    let mut first = 0;
+    for point in &self.points {
+      rolling_window.push(point.value);
+      match window {
+        Window::Points(count) => {
+          if rolling_window.len() > count {
+            rolling_window.pop();
+          }
+          if rolling_window.len() < count {
+            continue;
+          }
+        },
+        Window::Duration(duration) => {
+          let start = point.timestamp - duration;
+          while self.points[first].timestamp <= start {
+            rolling_window.pop();
+            first += 1;
+          }
+        },
+      }
+      points.push(Point::new(rolling_window.value(), point.timestamp));
+    }
+    self.with_points(points)
+  }
+
+  // A chart of the exponentially weighted moving average of this chart, where each point pulls the
+  // average towards its value. With a window of a number of points `n`, each point has a weight of
+  // `2 / (n + 1)`. With a window of a duration, the weight depends on how long it's been since the
+  // point before: the average covers about 63% of the way to a point that comes that duration after
+  // the last one.
+  pub fn ewma(&self, window: Window) -> Chart {
+    debug!("CALLING chart.ewma({:?})", window);
+    match window {
+      Window::Points(0) => panic!("Rolling windows need at least one point!"),
+      Window::Duration(duration) if duration <= Duration::zero() => {
+        panic!("Rolling windows need a duration greater than zero, got {:?}!", duration);
+      },
+      _ => {},
+    }
+
+    let mut points: Vec<Point> = vec![];
+    for point in &self.points {
+      let weight = match (window, points.last()) {
+        (_, None) => 1.0,
+        (Window::Points(count), _) => 2.0 / (count as f64 + 1.0),
+        (Window::Duration(duration), Some(last)) => {
+          let window_ns = nanoseconds_in(duration);
+          let elapsed_ns = duration_in_nanoseconds(last.timestamp, point.timestamp);
+          1.0 - (-elapsed_ns / window_ns).exp()
+        },
+      };
+
+      let average = match points.last() {
+        Some(last) => last.value + weight * (point.value - last.value),
+        None => point.value,
+      };
+      points.push(Point::new(average, point.timestamp));
+    }
+    self.with_points(points)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+  use chart::chart::Chart;
+  use chart::point::Point;
+  use chart::rolling::{Rolling, Window};
+  use chart::test_helpers::{at, chart_with_values};
+
+  fn values(chart: &Chart) -> Vec<f64> {
+    chart.points.iter().map(|point| point.value).collect()
+  }
+
+  #[test]
+  fn it_rolls_windows_of_a_number_of_points() {
+    let chart = chart_with_values(&[
+      (3.0, 0), (1.0, 10), (4.0, 20), (1.0, 30), (5.0, 40), (9.0, 50), (2.0, 60), (6.0, 70),
+    ], 3);
+
+    let mean = chart.rolling(Window::Points(3), Rolling::Mean);
+    assert_eq!(mean.points[0], Point::new(8.0 / 3.0, at(20)));
+    assert_eq!(values(&mean).len(), 6);
+    assert_eq!(mean.get_value(at(45)), Some((10.0 / 3.0 + 5.0) / 2.0));
+
+    for &(count, rolling, ref expected) in &[
+      (3, Rolling::Min, vec![1.0, 1.0, 1.0, 1.0, 2.0, 2.0]),
+      (3, Rolling::Max, vec![4.0, 4.0, 5.0, 9.0, 9.0, 9.0]),
+      (2, Rolling::Sum, vec![4.0, 5.0, 5.0, 6.0, 14.0, 11.0, 8.0]),
+      (3, Rolling::Median, vec![3.0, 1.0, 4.0, 5.0, 5.0, 6.0]),
+      (4, Rolling::Median, vec![2.0, 2.5, 4.5, 3.5, 5.5]),
+    ] {
+      let rolled = chart.rolling(Window::Points(count), rolling);
+      assert_eq!(&values(&rolled), expected, "{:?} of {} points", rolling, count);
+    }
+  }
+
+  #[test]
+  fn it_recovers_from_values_that_are_not_finite() {
+    let chart = chart_with_values(&[
+      (1.0, 0), (f64::NAN, 10), (1.0, 20), (1.0, 30), (1.0, 40), (1.0, 50),
+    ], 3);
+    let mean = values(&chart.rolling(Window::Points(2), Rolling::Mean));
+    assert!(mean[0].is_nan() && mean[1].is_nan());
+    assert_eq!(&mean[2..], [1.0, 1.0, 1.0]);
+
+    let chart = chart_with_values(&[(1.0, 0), (f64::INFINITY, 10), (1.0, 20), (1.0, 30)], 3);
+    let sum = chart.rolling(Window::Points(2), Rolling::Sum);
+    assert_eq!(values(&sum), [f64::INFINITY, f64::INFINITY, 2.0]);
+  }
+
+  #[test]
+  fn it_keeps_rolling_sums_from_drifting() {
+    let chart = chart_with_values(&[(1e16, 0), (1.0, 10), (1.0, 20), (1.0, 30)], 3);
+    let sum = chart.rolling(Window::Points(2), Rolling::Sum);
+    assert_eq!(values(&sum)[1..], [2.0, 2.0]);
+  }
+
+  #[test]
+  fn it_rolls_windows_of_a_duration() {
+    let chart = chart_with_values(&[
+      (3.0, 0), (1.0, 5), (4.0, 6), (1.0, 30), (5.0, 31), (9.0, 32), (2.0, 60), (6.0, 70),
+    ], 3);
+
+    let window = Window::Duration(Duration::seconds(10));
+    for &(rolling, ref expected) in &[
+      (Rolling::Sum, vec![3.0, 4.0, 8.0, 1.0, 6.0, 15.0, 2.0, 6.0]),
+      (Rolling::Max, vec![3.0, 3.0, 4.0, 1.0, 5.0, 9.0, 2.0, 6.0]),
+      (Rolling::Min, vec![3.0, 1.0, 1.0, 1.0, 1.0, 1.0, 2.0, 6.0]),
+      (Rolling::Mean, vec![3.0, 2.0, 8.0 / 3.0, 1.0, 3.0, 5.0, 2.0, 6.0]),
+    ] {
+      assert_eq!(&values(&chart.rolling(window, rolling)), expected, "{:?}", rolling);
+    }
+
+    // The new chart has its own index
+    let sum = chart.rolling(window, Rolling::Sum);
+    assert_eq!(sum.get_value(at(31)), Some(6.0));
+    assert_eq!(sum.get_value(at(65)), Some(4.0));
+  }
+
+  #[test]
+  fn it_finds_exponentially_weighted_moving_averages() {
+    let chart = chart_with_values(&[(0.0, 0), (10.0, 10), (10.0, 20), (10.0, 30)], 3);
+
+    let ewma = chart.ewma(Window::Points(3));
+    assert_eq!(values(&ewma), [0.0, 5.0, 7.5, 8.75]);
+
+    let ewma = chart.ewma(Window::Duration(Duration::seconds(10)));
+    let remaining = (-1.0f64).exp();
+    let expected = [0.0, 10.0 * (1.0 - remaining), 10.0 * (1.0 - remaining * remaining)];
+    for (value, expected) in values(&ewma).iter().zip(expected.iter()) {
+      assert!((value - expected).abs() < 1e-9);
+    }
+  }
+}
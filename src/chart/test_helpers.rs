@@ -0,0 +1,35 @@
+// Charts for the tests of each part of the chart module to share.
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use chart::chart::Chart;
+use chart::point::Point;
+
+// `seconds` after 9:00 on 2018-01-01, where the charts in the tests start.
+pub fn at(seconds: i64) -> DateTime<Utc> {
+  Utc.ymd(2018, 1, 1).and_hms(9, 0, 0) + Duration::seconds(seconds)
+}
+
+// A chart with a point for each `(value, seconds)`, at that many seconds after 9:00.
+pub fn chart_with_values(values: &[(f64, i64)], max_index_node_capacity: usize) -> Chart {
+  let points = values.iter().map(|&(value, seconds)| Point::new(value, at(seconds))).collect();
+  Chart::new(points, max_index_node_capacity)
+}
+
+// A chart with a point every `spacing` from 9:00, with the given values.
+pub fn chart_with_a_point_every<I>(
+  spacing: Duration,
+  values: I,
+  max_index_node_capacity: usize,
+) -> Chart where I: IntoIterator<Item=f64> {
+  let points = values.into_iter()
+    .enumerate()
+    .map(|(position, value)| Point::new(value, at(0) + spacing * position as i32))
+    .collect();
+  Chart::new(points, max_index_node_capacity)
+}
+
+// A chart with a point every minute from 9:00, with `length` values counting up from `first`.
+pub fn chart_counting_up_every_minute(first: usize, length: usize) -> Chart {
+  let values = (first..first + length).map(|value| value as f64);
+  chart_with_a_point_every(Duration::minutes(1), values, 3)
+}